@@ -30,35 +30,64 @@ impl BallTag {
             BallTag::Player => None,
         }
     }
+
+    /// stable numeric id used in saved data
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            BallTag::Red => 0,
+            BallTag::Blue => 1,
+            BallTag::Green => 2,
+            BallTag::Yellow => 3,
+            BallTag::Player => 4,
+        }
+    }
+
+    pub fn from_u8(id: u8) -> Option<BallTag> {
+        match id {
+            0 => Some(BallTag::Red),
+            1 => Some(BallTag::Blue),
+            2 => Some(BallTag::Green),
+            3 => Some(BallTag::Yellow),
+            4 => Some(BallTag::Player),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Component)]
 pub struct Ball {
+    pub radius: f32,
     pub drag_coefficient: f32,
     pub class: BallTag,
 }
+/// Mesh & material stores, only present when the world is rendered
+pub struct SceneAssets<'a> {
+    pub meshes: &'a mut Assets<Mesh>,
+    pub materials: &'a mut Assets<StandardMaterial>,
+}
+
 impl Ball {
+    /// Spawns the ball's physics, adding its mesh & material when `assets`
+    /// are given
     pub fn spawn<'a>(
         radius: f32,
         position: Vec3,
         velocity: Vec3,
         tag: BallTag,
         parent: &'a mut ChildBuilder<'_>,
-        meshes: &'a mut ResMut<Assets<Mesh>>,
-        materials: &'a mut ResMut<Assets<StandardMaterial>>,
+        assets: Option<&mut SceneAssets>,
     ) -> EntityCommands<'a> {
-        let entity = parent.spawn((
-            // Rendering components
-            PbrBundle {
-                mesh: meshes.add(Mesh::from(Sphere { radius: radius })),
-                material: materials.add(StandardMaterial {
-                    base_color: tag.color(),
-                    ..Default::default()
-                }),
-                transform: Transform::from_translation(position),
-                ..Default::default()
-            },
-            // Physics components
+        let mut entity = parent.spawn(Ball::physics(radius, position, velocity, tag));
+        if let Some(assets) = assets {
+            entity.insert(Ball::render(radius, tag, assets));
+        }
+        entity
+    }
+
+    /// Everything the simulation needs
+    pub fn physics(radius: f32, position: Vec3, velocity: Vec3, tag: BallTag) -> impl Bundle {
+        (
+            TransformBundle::from_transform(Transform::from_translation(position)),
             Collider::ball(radius),
             RigidBody::Dynamic,
             Restitution {
@@ -67,46 +96,71 @@ impl Ball {
             },
             Velocity::linear(velocity),
             LockedAxes::TRANSLATION_LOCKED_Y,
-            // Other
             Ball {
+                radius,
                 drag_coefficient: if tag == BallTag::Player { 0.1 } else { 0.01 },
                 class: tag,
             },
-        ));
-        entity
+        )
+    }
+
+    /// Mesh, material & visibility to draw the ball with
+    pub fn render(radius: f32, tag: BallTag, assets: &mut SceneAssets) -> impl Bundle {
+        (
+            assets.meshes.add(Mesh::from(Sphere { radius })),
+            assets.materials.add(StandardMaterial {
+                base_color: tag.color(),
+                ..Default::default()
+            }),
+            VisibilityBundle::default(),
+        )
     }
 
     /// Determines if a ball is the the quadrant
     /// associated with its class
     pub fn correct_quadrant(&self, x: f32, z: f32) -> bool {
+        self.correct_quadrant_with_margin(x, z, 0.)
+    }
+
+    /// Same as `correct_quadrant` but the ball must also be at
+    /// least `margin` away from the quadrant's boundary
+    pub fn correct_quadrant_with_margin(&self, x: f32, z: f32, margin: f32) -> bool {
         if self.class == BallTag::Player {
             false
         } else {
             let (t_x, t_z) = self.class.target_quadrant().unwrap();
-            x * t_x as f32 > 0. && z * t_z as f32 > 0.
+            x * t_x as f32 > margin && z * t_z as f32 > margin
+        }
+    }
+
+    /// Distance from (x, z) to the nearest point of the ball's
+    /// target quadrant, 0 when already inside it
+    pub fn distance_to_quadrant(&self, x: f32, z: f32) -> f32 {
+        match self.class.target_quadrant() {
+            None => 0.,
+            Some((t_x, t_z)) => {
+                let d_x = (-x * t_x as f32).max(0.);
+                let d_z = (-z * t_z as f32).max(0.);
+                (d_x * d_x + d_z * d_z).sqrt()
+            }
         }
     }
 }
 
 #[derive(Component)]
-pub struct ControllableBall {}
+pub struct ControllableBall {
+    /// which of the scene's teams the ball plays for
+    pub team: usize,
+}
 impl ControllableBall {
-    pub fn spawn(
+    pub fn spawn<'a>(
         position: Vec3,
-        parent: &mut ChildBuilder,
-        meshes: &mut ResMut<Assets<Mesh>>,
-        materials: &mut ResMut<Assets<StandardMaterial>>,
-    ) -> Entity {
-        Ball::spawn(
-            1.5,
-            position,
-            Vec3::ZERO,
-            BallTag::Player,
-            parent,
-            meshes,
-            materials,
-        )
-        .insert(ControllableBall {})
-        .id()
+        team: usize,
+        parent: &'a mut ChildBuilder<'_>,
+        assets: Option<&mut SceneAssets>,
+    ) -> EntityCommands<'a> {
+        let mut entity = Ball::spawn(1.5, position, Vec3::ZERO, BallTag::Player, parent, assets);
+        entity.insert(ControllableBall { team });
+        entity
     }
 }
@@ -0,0 +1,144 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+
+use crate::features::system::CameraController;
+use crate::scenes::ball_game_scene::{BallGameScene, ARENA_HALF_SIZE};
+
+/// how quickly the camera closes in on its target, per second
+const SMOOTHING: f32 = 4.0;
+const TOP_DOWN_HEIGHT: f32 = 70.0;
+/// chase-cam position relative to the player ball
+const CHASE_OFFSET: Vec3 = Vec3::new(0.0, 18.0, 22.0);
+/// keys that fly the camera while the mouse is locked
+const FLY_KEYS: [KeyCode; 6] = [
+    KeyCode::KeyW,
+    KeyCode::KeyA,
+    KeyCode::KeyS,
+    KeyCode::KeyD,
+    KeyCode::ShiftLeft,
+    KeyCode::Space,
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CameraView {
+    /// WASD + mouse, see `apply_system_inputs`
+    #[default]
+    Free,
+    /// the whole grid of scenes
+    Overview,
+    /// straight down onto the focused scene's arena
+    TopDown,
+    /// behind the focused scene's player ball
+    Chase,
+}
+
+/// What the camera is looking at
+#[derive(Resource, Default, Debug)]
+pub struct CameraFocus {
+    pub view: CameraView,
+    /// id of the focused scene
+    pub scene: usize,
+}
+
+/// 1-4 | free, overview, top-down & chase views,
+/// [ / ] | focus the previous/next scene
+pub fn camera_focus_inputs(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut mouse_motion_events: EventReader<MouseMotion>,
+    mut focus: ResMut<CameraFocus>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    scene_query: Query<&BallGameScene>,
+) {
+    let views = [
+        (KeyCode::Digit1, CameraView::Free),
+        (KeyCode::Digit2, CameraView::Overview),
+        (KeyCode::Digit3, CameraView::TopDown),
+        (KeyCode::Digit4, CameraView::Chase),
+    ];
+    for (key, view) in views {
+        if keyboard_input.just_pressed(key) {
+            focus.view = view;
+        }
+    }
+
+    let n_scenes = scene_query.iter().count().max(1);
+    if keyboard_input.just_pressed(KeyCode::BracketRight) {
+        focus.scene = (focus.scene + 1) % n_scenes;
+    }
+    if keyboard_input.just_pressed(KeyCode::BracketLeft) {
+        focus.scene = (focus.scene + n_scenes - 1) % n_scenes;
+    }
+
+    // flying the camera around, or ESC handing it the mouse, takes it
+    // out of any follow mode
+    let flying = mouse_motion_events
+        .read()
+        .filter(|motion| motion.delta != Vec2::ZERO)
+        .count()
+        > 0
+        || FLY_KEYS.iter().any(|key| keyboard_input.pressed(*key));
+    if let Ok(window) = q_windows.get_single() {
+        let grabbed = keyboard_input.just_pressed(KeyCode::Escape);
+        if window.cursor.grab_mode == CursorGrabMode::Locked && (flying || grabbed) {
+            focus.view = CameraView::Free;
+        }
+    }
+}
+
+/// Where the camera should be for the current view, `None` in free mode
+fn target_transform(
+    focus: &CameraFocus,
+    scene_query: &Query<(&BallGameScene, &GlobalTransform)>,
+    ball_query: &Query<&GlobalTransform, Without<CameraController>>,
+) -> Option<Transform> {
+    let focused = scene_query
+        .iter()
+        .find(|(scene, _)| scene.id == focus.scene);
+    match focus.view {
+        CameraView::Free => None,
+        CameraView::Overview => {
+            let centers = scene_query
+                .iter()
+                .map(|(_, transform)| transform.translation())
+                .collect::<Vec<Vec3>>();
+            let min = centers.iter().fold(Vec3::MAX, |a, b| a.min(*b));
+            let max = centers.iter().fold(Vec3::MIN, |a, b| a.max(*b));
+            let center = (min + max) / 2.;
+            let extent = (max - min).max_element() + 2. * ARENA_HALF_SIZE;
+            let eye = center + Vec3::new(0., extent * 0.9, extent * 0.45);
+            Some(Transform::from_translation(eye).looking_at(center, Vec3::Y))
+        }
+        CameraView::TopDown => {
+            let (_, transform) = focused?;
+            let center = transform.translation();
+            let eye = center + Vec3::Y * TOP_DOWN_HEIGHT;
+            // up on screen is -z, the direction the player's up key moves
+            Some(Transform::from_translation(eye).looking_at(center, Vec3::NEG_Z))
+        }
+        CameraView::Chase => {
+            let (scene, _) = focused?;
+            let player = ball_query.get(scene.lead().ball).ok()?.translation();
+            Some(Transform::from_translation(player + CHASE_OFFSET).looking_at(player, Vec3::Y))
+        }
+    }
+}
+
+/// Eases the camera towards the current view's target
+pub fn update_camera_focus(
+    time: Res<Time>,
+    focus: Res<CameraFocus>,
+    mut camera_query: Query<&mut Transform, With<CameraController>>,
+    scene_query: Query<(&BallGameScene, &GlobalTransform)>,
+    ball_query: Query<&GlobalTransform, Without<CameraController>>,
+) {
+    let Some(target) = target_transform(&focus, &scene_query, &ball_query) else {
+        return;
+    };
+    let Ok(mut camera) = camera_query.get_single_mut() else {
+        return;
+    };
+    let t = 1. - (-SMOOTHING * time.delta_seconds()).exp();
+    camera.translation = camera.translation.lerp(target.translation, t);
+    camera.rotation = camera.rotation.slerp(target.rotation, t);
+}
@@ -0,0 +1,128 @@
+//! Hand-coded sorting strategy: go behind the nearest unsorted ball,
+//! relative to its target quadrant, and push it in
+
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+use crate::features::ball::Ball;
+use crate::features::player_controllers::ControllerType;
+use crate::scenes::ball_game_scene::{BallGameScene, ARENA_HALF_SIZE};
+use crate::util::config::Config;
+
+/// how far into its quadrant a ball is pushed before it counts as done
+const SORT_MARGIN: f32 = 3.0;
+/// gap kept between the player & ball while lining up
+const LINE_UP_GAP: f32 = 1.0;
+/// distance from the line-up point at which the push starts
+const LINE_UP_TOLERANCE: f32 = 1.5;
+/// velocity the expert steers towards
+const TARGET_SPEED: f32 = 20.0;
+/// fraction of a unit vector a button needs, ~ sin(22.5°) for 8 directions
+const BUTTON_THRESHOLD: f32 = 0.38;
+/// steering below this is close enough, so the expert doesn't jitter
+const STEER_DEADZONE: f32 = 1.0;
+
+/// Which scenes the expert plays, from `expert.scenes`
+#[derive(Resource, Debug, Default)]
+pub struct ExpertScenes {
+    /// every scene when `None`
+    pub scenes: Option<HashSet<usize>>,
+    /// save the expert's steps as demonstrations
+    pub record: bool,
+}
+
+impl ExpertScenes {
+    /// Reads `expert.scenes`, a comma separated list of scene ids or `all`.
+    /// `None` when no scene is set to use the expert
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let scenes = config.get("expert.scenes", String::new());
+        let record = config.get("expert.record", false);
+        match scenes.trim() {
+            "" => None,
+            "all" => Some(ExpertScenes {
+                scenes: None,
+                record,
+            }),
+            ids => Some(ExpertScenes {
+                scenes: Some(
+                    ids.split(',')
+                        .filter_map(|id| id.trim().parse().ok())
+                        .collect(),
+                ),
+                record,
+            }),
+        }
+    }
+}
+
+/// Hands the configured scenes to the expert
+pub fn assign_expert_scenes(expert: Res<ExpertScenes>, mut scene_query: Query<&mut BallGameScene>) {
+    for mut scene in scene_query.iter_mut() {
+        if expert
+            .scenes
+            .as_ref()
+            .map_or(true, |s| s.contains(&scene.id))
+        {
+            scene.controller = ControllerType::Scripted {
+                record: expert.record,
+            };
+        }
+    }
+}
+
+/// Steering direction as (up, down, left, right); up is -z
+fn to_buttons(steer: Vec2) -> (bool, bool, bool, bool) {
+    if steer.length() < STEER_DEADZONE {
+        return (false, false, false, false);
+    }
+    let steer = steer.normalize();
+    (
+        steer.y < -BUTTON_THRESHOLD,
+        steer.y > BUTTON_THRESHOLD,
+        steer.x < -BUTTON_THRESHOLD,
+        steer.x > BUTTON_THRESHOLD,
+    )
+}
+
+/// Picks the expert's (up, down, left, right) for one scene from
+/// scene-local positions on the ground plane
+pub fn expert_action(
+    player: (Vec2, Vec2, f32),
+    balls: &[(Vec2, &Ball)],
+) -> (bool, bool, bool, bool) {
+    let (position, velocity, player_radius) = player;
+
+    // nearest ball still outside its quadrant
+    let target = balls
+        .iter()
+        .filter(|(p, ball)| !ball.correct_quadrant_with_margin(p.x, p.y, SORT_MARGIN))
+        .min_by(|(a, _), (b, _)| {
+            a.distance_squared(position)
+                .total_cmp(&b.distance_squared(position))
+        });
+    let Some((ball_position, ball)) = target else {
+        // all sorted, brake
+        return to_buttons(-velocity);
+    };
+
+    // push towards the middle of the target quadrant
+    let (t_x, t_z) = ball.class.target_quadrant().unwrap();
+    let goal = Vec2::new(t_x as f32, t_z as f32) * ARENA_HALF_SIZE / 2.;
+    let push = (goal - *ball_position).normalize_or_zero();
+    let behind = *ball_position - push * (ball.radius + player_radius + LINE_UP_GAP);
+
+    let to_behind = behind - position;
+    let waypoint = if to_behind.length() < LINE_UP_TOLERANCE {
+        // lined up, drive through the ball
+        *ball_position + push
+    } else if (position - *ball_position).dot(push) > 0. {
+        // on the wrong side, go around the ball rather than into it
+        let side = push.perp() * (position - *ball_position).dot(push.perp()).signum();
+        *ball_position + side * (ball.radius + player_radius) * 2.
+    } else {
+        behind
+    };
+
+    let desired = (waypoint - position).normalize_or_zero() * TARGET_SPEED;
+    to_buttons(desired - velocity)
+}
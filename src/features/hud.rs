@@ -0,0 +1,207 @@
+use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+use bevy::prelude::*;
+use bevy_rapier3d::render::DebugRenderContext;
+
+use crate::features::ball::Ball;
+use crate::features::player_controllers::ControllerType;
+use crate::modeling::TrainStats;
+use crate::scenes::ball_game_scene::{BallGameScene, ARENA_HALF_SIZE};
+use crate::util::episode::EpisodeConfig;
+use crate::util::events::PolicyUpdatedEvent;
+use crate::util::resources::{SimulationTimer, WorldState};
+
+/// Root of the screen-space HUD
+#[derive(Component)]
+pub struct HudRoot;
+
+#[derive(Component)]
+pub struct HudText;
+
+/// Label floating above a scene's arena
+#[derive(Component)]
+pub struct SceneLabel(pub Entity);
+
+/// Most recent training update, shown on the HUD
+#[derive(Resource, Default)]
+pub struct HudStats {
+    pub last_update: Option<(u64, TrainStats)>,
+}
+
+/// Spawns the HUD panel & one label per scene, hidden until F2
+pub fn setup_hud(mut commands: Commands, scene_query: Query<Entity, With<BallGameScene>>) {
+    let text_style = TextStyle {
+        font_size: 18.0,
+        ..Default::default()
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(10.0),
+                    left: Val::Px(10.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    ..Default::default()
+                },
+                background_color: Color::srgba(0.0, 0.0, 0.0, 0.5).into(),
+                visibility: Visibility::Hidden,
+                ..Default::default()
+            },
+            HudRoot,
+        ))
+        .with_children(|parent| {
+            parent.spawn((TextBundle::from_section("", text_style.clone()), HudText));
+        });
+
+    for scene in scene_query.iter() {
+        commands.spawn((
+            TextBundle::from_section("", text_style.clone()).with_style(Style {
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            }),
+            SceneLabel(scene),
+            HudRoot,
+        ));
+    }
+}
+
+/// Keeps the latest training stats for the HUD
+pub fn collect_hud_stats(
+    mut hud_stats: ResMut<HudStats>,
+    mut policy_reader: EventReader<PolicyUpdatedEvent>,
+) {
+    if let Some(update) = policy_reader.read().last() {
+        hud_stats.last_update = Some((update.round, update.stats.clone()));
+    }
+}
+
+/// Shows the HUD whenever the rapier debug render is on (F2)
+pub fn sync_hud_visibility(
+    debug_render_state: Res<DebugRenderContext>,
+    mut hud_query: Query<&mut Visibility, With<HudRoot>>,
+) {
+    if !debug_render_state.is_changed() {
+        return;
+    }
+    let visibility = match debug_render_state.enabled {
+        true => Visibility::Inherited,
+        false => Visibility::Hidden,
+    };
+    for mut v in hud_query.iter_mut() {
+        *v = visibility;
+    }
+}
+
+fn sorted_count<'a>(balls: impl Iterator<Item = (&'a Transform, &'a Ball)>, margin: f32) -> usize {
+    balls
+        .filter(|(t, b)| b.correct_quadrant_with_margin(t.translation.x, t.translation.z, margin))
+        .count()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_hud(
+    debug_render_state: Res<DebugRenderContext>,
+    diagnostics: Res<DiagnosticsStore>,
+    simulation_timer: Res<SimulationTimer>,
+    world_state: Res<WorldState>,
+    hud_stats: Res<HudStats>,
+    episode_config: Res<EpisodeConfig>,
+    scene_query: Query<&BallGameScene>,
+    ball_query: Query<(&Transform, &Ball)>,
+    mut text_query: Query<&mut Text, With<HudText>>,
+) {
+    if !debug_render_state.enabled {
+        return;
+    }
+    let fps = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.smoothed())
+        .unwrap_or(0.);
+
+    let (mut sorted, mut total, mut completed) = (0, 0, 0);
+    for scene in scene_query.iter() {
+        let balls = ball_query.iter_many(&scene.game_balls);
+        sorted += sorted_count(balls, episode_config.completion_margin);
+        total += scene.game_balls.len();
+        completed += scene.completed.is_some() as usize;
+    }
+    let episode = scene_query.iter().map(|s| s.episode).max().unwrap_or(0);
+
+    let mut lines = vec![
+        format!("FPS {:.0}", fps),
+        format!(
+            "episode {}  {:.1}s / {:.0}s",
+            episode,
+            simulation_timer.timer.elapsed_secs(),
+            simulation_timer.timer.duration().as_secs_f32()
+        ),
+        format!(
+            "sorted {}/{}  scenes done {}/{}  score {:.0}",
+            sorted,
+            total,
+            completed,
+            scene_query.iter().count(),
+            world_state.agg_score
+        ),
+    ];
+    match &hud_stats.last_update {
+        Some((round, stats)) => lines.push(format!(
+            "update {}: loss {:.3}  entropy {:.3}  grad norm {:.3}  steps {}",
+            round, stats.policy_loss, stats.entropy, stats.grad_norm, stats.steps
+        )),
+        None => lines.push("no updates yet".to_string()),
+    }
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+/// Moves each scene's label above the far edge of its arena
+pub fn update_scene_labels(
+    debug_render_state: Res<DebugRenderContext>,
+    episode_config: Res<EpisodeConfig>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    scene_query: Query<(&BallGameScene, &GlobalTransform)>,
+    ball_query: Query<(&Transform, &Ball)>,
+    mut label_query: Query<(&SceneLabel, &mut Text, &mut Style)>,
+) {
+    if !debug_render_state.enabled {
+        return;
+    }
+    let Some((camera, camera_transform)) = camera_query.iter().find(|(c, _)| c.is_active) else {
+        return;
+    };
+    for (label, mut text, mut style) in label_query.iter_mut() {
+        let Ok((scene, scene_transform)) = scene_query.get(label.0) else {
+            continue;
+        };
+        let anchor = scene_transform.translation() + Vec3::new(0., 5., -ARENA_HALF_SIZE);
+        // hide labels behind the camera
+        let Some(position) = camera.world_to_viewport(camera_transform, anchor) else {
+            style.display = Display::None;
+            continue;
+        };
+        style.display = Display::Flex;
+        style.left = Val::Px(position.x);
+        style.top = Val::Px(position.y);
+
+        let balls = ball_query.iter_many(&scene.game_balls);
+        let sorted = sorted_count(balls, episode_config.completion_margin);
+        let who = match scene.controller {
+            ControllerType::Keyboard => "human",
+            ControllerType::Scripted { .. } => "expert",
+            ControllerType::AI { .. } => "ai",
+        };
+        text.sections[0].value = match scene.completed {
+            Some(t) => format!("#{} {} sorted in {:.1}s", scene.id, who, t),
+            None => format!(
+                "#{} {} {}/{}",
+                scene.id,
+                who,
+                sorted,
+                scene.game_balls.len()
+            ),
+        };
+    }
+}
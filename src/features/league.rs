@@ -0,0 +1,377 @@
+//! Self-play league: a pool of frozen checkpoints kept on disk with Elo
+//! ratings. Each round the learner plays opponents sampled from the pool,
+//! a few scenes host matches between pool members, and every result
+//! updates the ratings
+
+use bevy::prelude::*;
+use rand::Rng;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::features::ball::Ball;
+use crate::features::versus::{Matchup, Opponent, Seat, Versus, VersusGoal, LEARNER_TEAM};
+use crate::modeling::ModelResource;
+use crate::scenes::ball_game_scene::BallGameScene;
+use crate::util::config::Config;
+use crate::util::episode::{sorted_fraction, EpisodeConfig};
+use crate::util::events::{PolicyUpdatedEvent, SimulationEndedEvent};
+use crate::util::run::RunDir;
+
+const RATINGS_FILE: &str = "ratings.csv";
+/// rating the learner & the first member of a new league start at
+const INITIAL_RATING: f64 = 1000.0;
+/// keeps some chance of sampling members the learner is far from
+const MIN_SAMPLE_WEIGHT: f64 = 0.05;
+/// number of members printed on the leaderboard
+const LEADERBOARD_SIZE: usize = 10;
+
+/// A frozen checkpoint in the league
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub name: String,
+    pub path: PathBuf,
+    pub rating: f64,
+    pub games: u64,
+}
+
+#[derive(Resource)]
+pub struct League {
+    /// where the checkpoints & ratings are kept, shared between runs
+    pub dir: PathBuf,
+    pub members: Vec<Member>,
+    /// the policy being trained, not saved with the members. Joins the
+    /// league at the rating of its newest member
+    pub learner: Member,
+    /// members in the `Opponent` pool this round, in pool order
+    pub loaded: Vec<usize>,
+    /// Elo K-factor
+    pub k: f64,
+    /// members the learner plays each round
+    pub opponents: usize,
+    /// scenes given to matches between two members
+    pub exhibition_scenes: usize,
+    /// rounds between adding a snapshot of the learner
+    pub snapshot_every: u64,
+}
+
+impl League {
+    /// Reads `league.*` & the ratings of the league in `league.dir`, if
+    /// any. The learner starts where the newest member left off, so its
+    /// snapshots enter on the same scale as the rest of the pool
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let dir = PathBuf::from(config.get("league.dir", "league".to_string()));
+        let mut league = League {
+            members: Vec::new(),
+            learner: Member {
+                name: "learner".to_string(),
+                path: PathBuf::new(),
+                rating: INITIAL_RATING,
+                games: 0,
+            },
+            loaded: Vec::new(),
+            k: config.get("league.k", 32.0),
+            opponents: config.get("league.opponents", 4usize).max(1),
+            exhibition_scenes: config.get("league.exhibition_scenes", 4),
+            snapshot_every: config.get("league.snapshot_every", 10u64).max(1),
+            dir,
+        };
+        league.load_ratings()?;
+        if let Some(newest) = league.members.last() {
+            league.learner.rating = newest.rating;
+        }
+        println!(
+            "League: {} members in {}",
+            league.members.len(),
+            league.dir.display()
+        );
+        Ok(league)
+    }
+
+    /// `name,path,rating,games` per member
+    fn load_ratings(&mut self) -> io::Result<()> {
+        let path = self.dir.join(RATINGS_FILE);
+        if !path.exists() {
+            return Ok(());
+        }
+        for line in BufReader::new(File::open(&path)?).lines().skip(1) {
+            let line = line?;
+            let fields = line.split(',').collect::<Vec<_>>();
+            let [name, member_path, rating, games] = fields[..] else {
+                eprintln!("League: skipping malformed rating {:?}", line);
+                continue;
+            };
+            self.members.push(Member {
+                name: name.to_string(),
+                path: PathBuf::from(member_path),
+                rating: rating.parse().unwrap_or(INITIAL_RATING),
+                games: games.parse().unwrap_or(0),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn save_ratings(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut file = BufWriter::new(File::create(self.dir.join(RATINGS_FILE))?);
+        writeln!(file, "name,path,rating,games")?;
+        for member in self.members.iter() {
+            writeln!(
+                file,
+                "{},{},{:.1},{}",
+                member.name,
+                member.path.display(),
+                member.rating,
+                member.games
+            )?;
+        }
+        file.flush()
+    }
+
+    /// Saves the learner as a new member, rated as the learner is now
+    pub fn snapshot(&mut self, model: &ModelResource, name: String) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}.pt", name));
+        model
+            .model
+            .save(&path)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        println!("League: added {}", name);
+        self.members.push(Member {
+            name,
+            path,
+            rating: self.learner.rating,
+            games: 0,
+        });
+        self.save_ratings()
+    }
+
+    fn seat_mut(&mut self, seat: Seat) -> Option<&mut Member> {
+        match seat {
+            Seat::Learner => Some(&mut self.learner),
+            Seat::Pool(i) => {
+                let member = *self.loaded.get(i)?;
+                self.members.get_mut(member)
+            }
+        }
+    }
+
+    /// Elo update for one game, `score` being 1 for a home win, 0.5 a draw
+    fn record(&mut self, matchup: Matchup, score: f64) {
+        let (Some(home), Some(away)) = (
+            self.seat_mut(matchup.home).map(|m| m.rating),
+            self.seat_mut(matchup.away).map(|m| m.rating),
+        ) else {
+            return;
+        };
+        let change = self.k * (score - expected_score(home, away));
+        for (seat, change) in [(matchup.home, change), (matchup.away, -change)] {
+            if let Some(member) = self.seat_mut(seat) {
+                member.rating += change;
+                member.games += 1;
+            }
+        }
+    }
+
+    /// Up to `opponents` distinct members, favouring those the learner
+    /// is about evenly matched with
+    fn sample_opponents(&self, rng: &mut impl Rng) -> Vec<usize> {
+        let mut candidates = (0..self.members.len())
+            .filter(|i| self.members[*i].path.exists())
+            .collect::<Vec<_>>();
+        let mut picked = Vec::new();
+        while picked.len() < self.opponents && !candidates.is_empty() {
+            let weights = candidates
+                .iter()
+                .map(|i| {
+                    let p = expected_score(self.learner.rating, self.members[*i].rating);
+                    p * (1.0 - p) + MIN_SAMPLE_WEIGHT
+                })
+                .collect::<Vec<_>>();
+            let mut r = rng.gen::<f64>() * weights.iter().sum::<f64>();
+            let mut choice = candidates.len() - 1;
+            for (j, w) in weights.iter().enumerate() {
+                if r < *w {
+                    choice = j;
+                    break;
+                }
+                r -= w;
+            }
+            picked.push(candidates.remove(choice));
+        }
+        picked
+    }
+
+    fn print_leaderboard(&self) {
+        let mut board = self.members.iter().collect::<Vec<_>>();
+        board.push(&self.learner);
+        board.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+        println!("League leaderboard:");
+        for (rank, member) in board.iter().take(LEADERBOARD_SIZE).enumerate() {
+            println!(
+                "{:>3}. {:<40} {:>7.1} ({} games)",
+                rank + 1,
+                member.name,
+                member.rating,
+                member.games
+            );
+        }
+    }
+}
+
+/// Chance `a` beats `b` under Elo
+fn expected_score(a: f64, b: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((b - a) / 400.0))
+}
+
+/// How the home side did, 1 for a win & 0.5 a draw, judged on the sorting
+/// itself rather than the shaped rewards. Under `Split` the side that
+/// sorted more of its own balls wins; under `Disrupt` the sorter scores
+/// the fraction of the balls it got sorted
+fn match_score(
+    scene: &BallGameScene,
+    versus: &Versus,
+    ball_query: &Query<(&Transform, &Ball)>,
+    margin: f32,
+) -> f64 {
+    // a completed scene had every ball sorted, whatever they did after
+    if scene.completed.is_some() {
+        return match versus.goal {
+            VersusGoal::Split => 0.5,
+            VersusGoal::Disrupt => 1.0,
+        };
+    }
+    let sorted = |team: usize| {
+        let balls = ball_query
+            .iter_many(&scene.game_balls)
+            .filter(|(_, ball)| versus.scores(team, ball.class));
+        sorted_fraction(balls, margin)
+    };
+    match versus.goal {
+        VersusGoal::Split => match sorted(LEARNER_TEAM).total_cmp(&sorted(1 - LEARNER_TEAM)) {
+            std::cmp::Ordering::Greater => 1.0,
+            std::cmp::Ordering::Equal => 0.5,
+            std::cmp::Ordering::Less => 0.0,
+        },
+        VersusGoal::Disrupt => sorted(LEARNER_TEAM) as f64,
+    }
+}
+
+/// Name a snapshot of the learner is saved under, unique across runs
+fn snapshot_name(run_dir: &RunDir, round: &str) -> String {
+    let run = run_dir
+        .path
+        .file_name()
+        .map_or("run".into(), |name| name.to_string_lossy());
+    format!("{}_{}", run, round)
+}
+
+/// Samples this round's opponents into the pool & seats every scene: the
+/// learner against a sampled member, or two members against each other
+fn schedule(
+    league: &mut League,
+    opponent: &mut Opponent,
+    model: &ModelResource,
+    scene_query: &mut Query<&mut BallGameScene>,
+) {
+    let mut rng = rand::thread_rng();
+    league.loaded = league.sample_opponents(&mut rng);
+    opponent.pool = league
+        .loaded
+        .iter()
+        .map(|i| ModelResource::new(&league.members[*i].path.to_string_lossy(), model.config))
+        .collect();
+    if opponent.pool.is_empty() {
+        return;
+    }
+
+    let n = opponent.pool.len();
+    let n_scenes = scene_query.iter().count();
+    let exhibitions = if n > 1 {
+        league.exhibition_scenes.min(n_scenes)
+    } else {
+        0
+    };
+    for mut scene in scene_query.iter_mut() {
+        let id = scene.id;
+        scene.matchup = if id + exhibitions >= n_scenes {
+            let home = rng.gen_range(0..n);
+            let away = (home + rng.gen_range(1..n)) % n;
+            Matchup {
+                home: Seat::Pool(home),
+                away: Seat::Pool(away),
+            }
+        } else {
+            Matchup {
+                home: Seat::Learner,
+                away: Seat::Pool(id % n),
+            }
+        };
+    }
+}
+
+/// Starts the league off with the untrained learner if it's empty, then
+/// schedules the first round
+pub fn start_league(
+    mut league: ResMut<League>,
+    mut opponent: ResMut<Opponent>,
+    model: Res<ModelResource>,
+    run_dir: Res<RunDir>,
+    mut scene_query: Query<&mut BallGameScene>,
+) {
+    if league.members.is_empty() {
+        if let Err(e) = league.snapshot(&model, snapshot_name(&run_dir, "init")) {
+            eprintln!("League: failed to add the learner: {}", e);
+            return;
+        }
+    }
+    schedule(&mut league, &mut opponent, &model, &mut scene_query);
+}
+
+/// Rates every scene's game before the scenes are reset
+pub fn score_league_matches(
+    mut event_reader: EventReader<SimulationEndedEvent>,
+    mut league: ResMut<League>,
+    versus: Res<Versus>,
+    episode_config: Res<EpisodeConfig>,
+    scene_query: Query<&BallGameScene>,
+    ball_query: Query<(&Transform, &Ball)>,
+) {
+    if event_reader.read().count() == 0 {
+        return;
+    }
+    for scene in scene_query.iter() {
+        let score = match_score(
+            scene,
+            &versus,
+            &ball_query,
+            episode_config.completion_margin,
+        );
+        league.record(scene.matchup, score);
+    }
+    if let Err(e) = league.save_ratings() {
+        eprintln!("League: failed to save ratings: {}", e);
+    }
+    league.print_leaderboard();
+}
+
+/// After each update, snapshots the learner when due & schedules the next round
+pub fn schedule_league_matches(
+    mut policy_reader: EventReader<PolicyUpdatedEvent>,
+    mut league: ResMut<League>,
+    mut opponent: ResMut<Opponent>,
+    model: Res<ModelResource>,
+    run_dir: Res<RunDir>,
+    mut scene_query: Query<&mut BallGameScene>,
+) {
+    let Some(update) = policy_reader.read().last() else {
+        return;
+    };
+    if (update.round + 1) % league.snapshot_every == 0 {
+        let name = snapshot_name(&run_dir, &format!("round{:05}", update.round));
+        if let Err(e) = league.snapshot(&model, name) {
+            eprintln!("League: failed to add the learner: {}", e);
+        }
+    }
+    schedule(&mut league, &mut opponent, &model, &mut scene_query);
+}
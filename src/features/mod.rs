@@ -9,6 +9,20 @@ pub mod ball {
 pub mod system_controls;
 pub use system_controls as system;
 
+pub mod camera_focus;
+
+pub mod expert;
+
+pub mod hud;
+
+pub mod league;
+
 pub mod player_controllers;
 
+pub mod policy_overlay;
+
+pub mod replay;
+
 pub mod ui;
+
+pub mod versus;
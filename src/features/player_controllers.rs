@@ -1,15 +1,73 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
-use nn::ModuleT;
+use rand::Rng;
 use tch::*;
 
 use crate::features::ball::*;
-use crate::modeling::ModelResource;
+use crate::features::camera_focus::{CameraFocus, CameraView};
+use crate::features::expert::expert_action;
+use crate::features::versus::{mirror_action, Matchup, Opponent, Seat, LEARNER_TEAM};
+use crate::modeling::dagger::Dagger;
+use crate::modeling::observation::*;
+use crate::modeling::{ModelResource, TeamPolicies};
 use crate::scenes::ball_game_scene::BallGameScene;
+use crate::util::config::Config;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ControllerType {
     Keyboard,
-    AI { training: bool },
+    AI {
+        training: bool,
+    },
+    /// the hand-coded expert, its steps saved as demonstrations when recording
+    Scripted {
+        record: bool,
+    },
+}
+
+impl ControllerType {
+    /// whether this scene's steps are collected for training
+    pub fn is_training(&self) -> bool {
+        matches!(self, ControllerType::AI { training: true })
+    }
+}
+
+/// What is done with the steps of a scene a human has taken over
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HumanSteps {
+    /// trained on along with the policy's own steps
+    Include,
+    /// dropped
+    Exclude,
+    /// kept apart & saved to the run's `demonstrations/`
+    Demonstration,
+}
+
+#[derive(Resource, Debug)]
+pub struct Takeover {
+    pub human_steps: HumanSteps,
+}
+
+impl Takeover {
+    /// Reads `takeover.human_steps`, one of `include`, `exclude` or `demonstration`
+    pub fn from_config(config: &Config) -> Self {
+        let human_steps = match config
+            .get("takeover.human_steps", "exclude".to_string())
+            .as_str()
+        {
+            "include" => HumanSteps::Include,
+            "demonstration" => HumanSteps::Demonstration,
+            "exclude" => HumanSteps::Exclude,
+            other => {
+                eprintln!(
+                    "Config: unknown takeover.human_steps {}, using exclude",
+                    other
+                );
+                HumanSteps::Exclude
+            }
+        };
+        Takeover { human_steps }
+    }
 }
 
 /// Human player input
@@ -21,22 +79,72 @@ fn get_keyboard_input(keyboard_input: &Res<ButtonInput<KeyCode>>) -> (bool, bool
     (up, down, left, right)
 }
 
-/// Gets movement actions from model over a batch of states
-fn get_ai_movement(model: &TrainableCModule, s: Tensor) -> Vec<(bool, bool, bool, bool)> {
+/// Gets (up, down, left, right) probabilities from model over a batch of
+/// states, along with the next hidden state of a recurrent policy
+fn get_ai_movement(
+    model: &ModelResource,
+    s: Tensor,
+    h: Option<Tensor>,
+) -> (Vec<[f32; 4]>, Option<Tensor>) {
     // Run model
-    let a_next = model
-        .forward_t(&s, false)
-        .sigmoid()
-        .to_device(Device::Cpu)
-        .to_kind(Kind::Float)
-        .gt(0.5);
+    let (logits, h_next) = no_grad(|| model.forward(&s, h.as_ref(), false));
+    let probs = logits.sigmoid().to_device(Device::Cpu).to_kind(Kind::Float);
 
     // extract output & return
-    Vec::<Vec<bool>>::try_from(a_next)
+    let probs = Vec::<Vec<f32>>::try_from(probs)
         .unwrap()
         .iter()
-        .map(|x| (x[0], x[1], x[2], x[3]))
-        .collect::<Vec<(bool, bool, bool, bool)>>()
+        .map(|x| [x[0], x[1], x[2], x[3]])
+        .collect::<Vec<[f32; 4]>>();
+    (probs, h_next)
+}
+
+/// Picks actions from probabilities, sampling while training so
+/// the policy can explore
+fn choose_action(probs: [f32; 4], sample: bool) -> (bool, bool, bool, bool) {
+    let mut rng = rand::thread_rng();
+    let pick = |p: f32| {
+        if sample {
+            rng.gen::<f32>() < p
+        } else {
+            p > 0.5
+        }
+    };
+    (
+        pick(probs[0]),
+        pick(probs[1]),
+        pick(probs[2]),
+        pick(probs[3]),
+    )
+}
+
+fn action_to_array(action: (bool, bool, bool, bool)) -> [f32; 4] {
+    [
+        action.0 as i32 as f32,
+        action.1 as i32 as f32,
+        action.2 as i32 as f32,
+        action.3 as i32 as f32,
+    ]
+}
+
+/// The scripted expert's action for a scene, from its balls' scene-local positions
+fn query_expert<'a>(
+    player: (&Velocity, &Transform, &Ball),
+    balls: impl Iterator<Item = (&'a Velocity, &'a Transform, &'a Ball)>,
+) -> (bool, bool, bool, bool) {
+    let flat = |v: Vec3| Vec2::new(v.x, v.z);
+    let balls = balls
+        .map(|(_, transform, ball)| (flat(transform.translation), ball))
+        .collect::<Vec<_>>();
+    let (velocity, transform, ball) = player;
+    expert_action(
+        (
+            flat(transform.translation),
+            flat(velocity.linvel),
+            ball.radius,
+        ),
+        &balls,
+    )
 }
 
 const PLAYER_SPEED: f32 = 250.0;
@@ -61,66 +169,247 @@ fn apply_movement(
     velocity.linvel += direction * time.delta_seconds();
 }
 
-// collect AI input
-fn push(velocity: &Velocity, transform: &Transform, target_quadrant: Option<(i8, i8)>) -> Vec<f32> {
-    let mut inputs = Vec::new();
-    inputs.push(velocity.linvel.x);
-    inputs.push(velocity.linvel.z);
-    inputs.push(transform.translation.x);
-    inputs.push(transform.translation.z);
-    if let Some((v1, v2)) = target_quadrant {
-        inputs.append(&mut vec![v1 as f32, v2 as f32]);
-    }
-    inputs
-}
+#[allow(clippy::too_many_arguments)]
 pub fn move_balls(
     keyboard_input: Res<ButtonInput<KeyCode>>,
     model_resource: Res<ModelResource>,
-    scene_query: Query<&BallGameScene>,
+    team: Option<Res<TeamPolicies>>,
+    takeover: Res<Takeover>,
+    dagger: Option<Res<Dagger>>,
+    opponent: Option<Res<Opponent>>,
+    mut scene_query: Query<&mut BallGameScene>,
     balls_query: Query<(&Velocity, &Transform, &Ball), Without<ControllableBall>>,
-    mut pball_query: Query<(&mut Velocity, &Transform), With<ControllableBall>>,
+    mut pball_query: Query<(&mut Velocity, &Transform, &Ball), With<ControllableBall>>,
     time: Res<Time>,
 ) {
-    // collect model input filtered for AI controlled scenes
-    let batch_states = Tensor::cat(
-        &(scene_query
+    let config = model_resource.config;
+    let mut models = TeamPolicies::with_lead(&model_resource, team.as_deref());
+    let n_learners = models.len();
+    if let Some(opponent) = &opponent {
+        models.extend(opponent.pool.iter());
+    }
+    // a team seated with a frozen policy is played by it, the learner's
+    // player `k` by its own policy or the lead's shared one
+    let policy_of = |k: usize, team: usize, matchup: Matchup| match matchup.seat(team) {
+        Seat::Pool(i) if opponent.is_some() && n_learners + i < models.len() => n_learners + i,
+        _ if n_learners > 1 => k,
+        _ => 0,
+    };
+
+    // collect each policy's model input, human played scenes included so
+    // their steps can be kept & the overlay shows what the policy would have done
+    let mut batch_states = (0..models.len()).map(|_| Vec::new()).collect::<Vec<_>>();
+    let mut batch_hidden = (0..models.len()).map(|_| Vec::new()).collect::<Vec<_>>();
+    for mut scene in scene_query.iter_mut() {
+        if scene.completed.is_some() {
+            continue;
+        }
+        let scene = &mut *scene;
+        let player_balls = scene
+            .players
             .iter()
-            .filter_map(|scene| match &scene.controller {
-                ControllerType::Keyboard => None,
-                ControllerType::AI { training: _ } => {
-                    let (p_velocity, p_transform) = pball_query.get_mut(scene.player_ball).unwrap();
-                    let mut inputs = Vec::new();
-                    inputs.append(&mut push(&*p_velocity, p_transform, None));
-                    for (velocity, transform, ball) in balls_query.iter_many(&scene.game_balls) {
-                        inputs.append(&mut push(velocity, transform, ball.class.target_quadrant()));
+            .map(|p| (p.ball, p.team))
+            .collect::<Vec<_>>();
+        for (k, player) in scene.players.iter_mut().enumerate() {
+            let (p_velocity, p_transform, p_ball) = pball_query.get(player.ball).unwrap();
+            // own team first, then the opposing one
+            let mut others = player_balls
+                .iter()
+                .filter(|(ball, _)| *ball != player.ball)
+                .collect::<Vec<_>>();
+            others.sort_by_key(|(_, team)| *team != player.team);
+            let teammates = pball_query
+                .iter_many(others.iter().map(|(ball, _)| *ball))
+                .map(|(velocity, transform, _)| (velocity, transform));
+            let balls = balls_query.iter_many(&scene.game_balls);
+            let observation = match config.observation {
+                ObservationType::Vector => {
+                    vector_observation((p_velocity, p_transform), teammates, balls)
+                }
+                ObservationType::Image => image_observation(
+                    (p_transform, p_ball),
+                    balls.map(|(_, transform, ball)| (transform, ball)),
+                ),
+            };
+            let policy = policy_of(k, player.team, scene.matchup);
+            // the opposing team sees the arena mirrored, as if it were the learner's
+            let observation = if policy >= n_learners && player.team != LEARNER_TEAM {
+                mirror_x(&observation, player_balls.len())
+            } else {
+                observation
+            };
+            batch_states[policy].push(player.memory.stack(observation, config.frame_stack));
+
+            if config.recurrent {
+                let h = match &player.memory.hidden {
+                    Some(h) => h.shallow_clone(),
+                    None => models[policy].initial_hidden(1),
+                };
+                if player.trajectory.state.is_empty() {
+                    player.trajectory.initial_hidden = Some(h.shallow_clone());
+                }
+                batch_hidden[policy].push(h);
+            }
+        }
+    }
+
+    // run models
+    let outputs = models
+        .iter()
+        .zip(batch_states.iter().zip(batch_hidden.iter()))
+        .map(|(model, (states, hidden))| {
+            if states.is_empty() {
+                (Vec::new(), None)
+            } else {
+                get_ai_movement(
+                    model,
+                    Tensor::cat(states, 0),
+                    config.recurrent.then(|| Tensor::cat(hidden, 0)),
+                )
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // apply movements
+    let mut next = vec![0; models.len()];
+    for mut scene in scene_query.iter_mut() {
+        if scene.completed.is_some() {
+            continue;
+        }
+        let scene = &mut *scene;
+        for k in 0..scene.players.len() {
+            let controller = scene.controller_of(k);
+            let team = scene.players[k].team;
+            let policy = policy_of(k, team, scene.matchup);
+            let i = next[policy];
+            next[policy] += 1;
+            let (batch_probs, batch_hidden) = &outputs[policy];
+            let probs = batch_probs[i];
+            let state = batch_states[policy][i].shallow_clone();
+
+            let player = &mut scene.players[k];
+            if let Some(h) = batch_hidden {
+                player.memory.hidden = Some(h.get(i as i64).unsqueeze(0));
+            }
+            player.last_probs = Some(probs);
+
+            let (mut p_velocity, p_transform, p_ball) = pball_query.get_mut(player.ball).unwrap();
+            let action = match controller {
+                _ if policy >= n_learners => match choose_action(probs, true) {
+                    action if team != LEARNER_TEAM => mirror_action(action),
+                    action => action,
+                },
+                ControllerType::Keyboard => {
+                    let action = get_keyboard_input(&keyboard_input);
+                    match takeover.human_steps {
+                        HumanSteps::Include => player
+                            .trajectory
+                            .push_human_step(state, action_to_array(action)),
+                        HumanSteps::Demonstration => player
+                            .demonstration
+                            .push_human_step(state, action_to_array(action)),
+                        HumanSteps::Exclude => {}
                     }
-                    Some(Tensor::from_slice(&inputs).view([1, (50 + 1) * 6 - 2]))
+                    action
                 }
-            })
-            .collect::<Vec<Tensor>>()),
-        0,
-    );
+                ControllerType::Scripted { record } => {
+                    let action = query_expert(
+                        (&*p_velocity, p_transform, p_ball),
+                        balls_query.iter_many(&scene.game_balls),
+                    );
+                    if record {
+                        player
+                            .demonstration
+                            .push_step(state, action_to_array(action));
+                    }
+                    action
+                }
+                ControllerType::AI { training } => {
+                    let mut action = choose_action(probs, training);
+                    // DAgger: the expert labels the state whatever is played
+                    if let Some(dagger) = &dagger {
+                        let expert = query_expert(
+                            (&*p_velocity, p_transform, p_ball),
+                            balls_query.iter_many(&scene.game_balls),
+                        );
+                        player
+                            .demonstration
+                            .push_step(state.shallow_clone(), action_to_array(expert));
+                        if rand::thread_rng().gen::<f64>() < dagger.beta {
+                            action = expert;
+                        }
+                    }
+                    if training {
+                        player.trajectory.push_step(state, action_to_array(action));
+                    }
+                    action
+                }
+            };
+            player.last_action = action;
+            apply_movement(
+                action.0,
+                action.1,
+                action.2,
+                action.3,
+                &mut p_velocity,
+                &time,
+            );
+        }
+    }
+}
 
-    // run model and apply movements
-    let batch_actions: Vec<(bool, bool, bool, bool)> =
-        get_ai_movement(&model_resource.model, batch_states);
-    let mut i = 0;
-    for scene in scene_query.iter() {
-        let (mut p_velocity, _) = pball_query.get_mut(scene.player_ball).unwrap();
-        let action = match &scene.controller {
-            ControllerType::Keyboard => get_keyboard_input(&keyboard_input),
-            ControllerType::AI { training: _ } => {
-                i += 1;
-                batch_actions[i - 1]
+/// H | hand the focused scene to the keyboard, or back to the AI
+pub fn toggle_takeover(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    focus: Res<CameraFocus>,
+    mut scene_query: Query<&mut BallGameScene>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyH) {
+        return;
+    }
+    for mut scene in scene_query.iter_mut() {
+        if scene.id != focus.scene {
+            continue;
+        }
+        // returns shouldn't carry over between the human & the policy
+        scene.players[0].trajectory.end_episode();
+        scene.players[0].demonstration.end_episode();
+        let controller = scene.controller;
+        scene.controller = match controller {
+            ControllerType::Keyboard => {
+                println!("Scene {} handed back to the AI", scene.id);
+                scene
+                    .paused_controller
+                    .take()
+                    .unwrap_or(ControllerType::AI { training: true })
+            }
+            controller => {
+                println!("Scene {} taken over, steer with the arrow keys", scene.id);
+                scene.paused_controller = Some(controller);
+                ControllerType::Keyboard
             }
         };
-        apply_movement(
-            action.0,
-            action.1,
-            action.2,
-            action.3,
-            &mut p_velocity,
-            &time,
-        );
     }
 }
+
+/// Demonstration mode: the human starts out playing the focused scene,
+/// seen top-down, with their steps saved as demonstrations
+pub fn start_demo(
+    mut focus: Option<ResMut<CameraFocus>>,
+    mut scene_query: Query<&mut BallGameScene>,
+) {
+    let scene_id = focus.as_ref().map_or(0, |f| f.scene);
+    if let Some(focus) = focus.as_mut() {
+        focus.view = CameraView::TopDown;
+    }
+    for mut scene in scene_query.iter_mut() {
+        if scene.id == scene_id {
+            scene.paused_controller = Some(scene.controller);
+            scene.controller = ControllerType::Keyboard;
+        }
+    }
+    println!(
+        "Demonstration mode: playing scene {}, steer with the arrow keys",
+        scene_id
+    );
+}
@@ -0,0 +1,101 @@
+use bevy::color::palettes::css::{GRAY, LIME, WHITE};
+use bevy::prelude::*;
+
+use crate::features::camera_focus::{CameraFocus, CameraView};
+use crate::features::system::CameraController;
+use crate::scenes::ball_game_scene::BallGameScene;
+
+/// length of an arrow at probability 1
+const ARROW_LENGTH: f32 = 6.0;
+/// height above the player ball arrows are drawn at
+const ARROW_HEIGHT: f32 = 1.5;
+
+/// Where the camera is looking on the ground plane, or right below
+/// it when looking up
+fn camera_focus(camera: &Transform) -> Vec3 {
+    let forward = camera.forward();
+    if forward.y < -1e-3 {
+        let t = -camera.translation.y / forward.y;
+        camera.translation + *forward * t
+    } else {
+        Vec3::new(camera.translation.x, 0., camera.translation.z)
+    }
+}
+
+/// V | toggle the policy overlay on the focused scene, or the one the
+/// free camera looks at, SHIFT+V | toggle it on every scene
+pub fn toggle_policy_overlay(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    focus: Option<Res<CameraFocus>>,
+    camera_query: Query<&Transform, With<CameraController>>,
+    mut scene_query: Query<(&mut BallGameScene, &GlobalTransform)>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyV) {
+        return;
+    }
+    if keyboard_input.pressed(KeyCode::ShiftLeft) {
+        let show = !scene_query.iter().any(|(scene, _)| scene.show_policy);
+        for (mut scene, _) in scene_query.iter_mut() {
+            scene.show_policy = show;
+        }
+        return;
+    }
+
+    if let Some(focus) = focus.filter(|f| matches!(f.view, CameraView::TopDown | CameraView::Chase))
+    {
+        for (mut scene, _) in scene_query.iter_mut() {
+            if scene.id == focus.scene {
+                scene.show_policy = !scene.show_policy;
+            }
+        }
+        return;
+    }
+    let Ok(camera) = camera_query.get_single() else {
+        return;
+    };
+    let focus = camera_focus(camera);
+    let nearest = scene_query.iter_mut().min_by(|(_, a), (_, b)| {
+        let da = a.translation().distance_squared(focus);
+        let db = b.translation().distance_squared(focus);
+        da.total_cmp(&db)
+    });
+    if let Some((mut scene, _)) = nearest {
+        scene.show_policy = !scene.show_policy;
+    }
+}
+
+/// Arrows from each shown scene's player balls, one per direction with
+/// length proportional to the policy's probability. Directions the
+/// player took are green, a ring marks the 0.5 threshold
+pub fn draw_policy_overlay(
+    mut gizmos: Gizmos,
+    scene_query: Query<&BallGameScene>,
+    ball_query: Query<&GlobalTransform>,
+) {
+    for scene in scene_query.iter() {
+        if !scene.show_policy {
+            continue;
+        }
+        for player in scene.players.iter() {
+            let Some(probs) = player.last_probs else {
+                continue;
+            };
+            let Ok(ball) = ball_query.get(player.ball) else {
+                continue;
+            };
+            let origin = ball.translation() + Vec3::Y * ARROW_HEIGHT;
+            let (up, down, left, right) = player.last_action;
+            let arrows = [
+                (Vec3::NEG_Z, probs[0], up),
+                (Vec3::Z, probs[1], down),
+                (Vec3::NEG_X, probs[2], left),
+                (Vec3::X, probs[3], right),
+            ];
+            for (direction, p, taken) in arrows {
+                let color = if taken { LIME } else { WHITE };
+                gizmos.arrow(origin, origin + direction * p * ARROW_LENGTH, color);
+            }
+            gizmos.circle(origin, Dir3::Y, 0.5 * ARROW_LENGTH, GRAY);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::features::ball::SceneAssets;
+use crate::scenes::ball_game_scene::*;
+use crate::util::recording::*;
+
+/// Recorded episode being played back
+#[derive(Resource)]
+pub struct Replay {
+    pub header: EpisodeHeader,
+    pub steps: Vec<StepRecord>,
+    /// index of the step being shown
+    pub frame: usize,
+    /// seconds into the episode, playback shows the last step taken by then
+    pub clock: f32,
+    pub playing: bool,
+    pub speed: f32,
+}
+
+#[derive(Component)]
+pub struct ReplayOverlay;
+
+impl Replay {
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let (header, steps) = EpisodeReader::open(path)?.read_all()?;
+        println!(
+            "Replaying scene {} (seed {}), {} steps",
+            header.scene,
+            header.seed,
+            steps.len()
+        );
+        Ok(Replay {
+            clock: steps.first().map_or(0., |s| s.time),
+            header,
+            steps,
+            frame: 0,
+            playing: true,
+            speed: 1.,
+        })
+    }
+
+    pub fn current(&self) -> usize {
+        self.frame
+    }
+
+    fn last_frame(&self) -> usize {
+        self.steps.len().saturating_sub(1)
+    }
+
+    /// Moves the clock to `clock` & shows the last step taken at or before it.
+    /// Steps are recorded once per frame at whatever rate the game ran, so
+    /// they're found by their time rather than counted
+    fn seek(&mut self, clock: f32) {
+        let start = self.steps.first().map_or(0., |s| s.time);
+        let end = self.steps.last().map_or(0., |s| s.time);
+        let clock = clock.clamp(start, end);
+        self.clock = clock;
+        self.frame = self
+            .steps
+            .partition_point(|s| s.time <= clock)
+            .saturating_sub(1);
+    }
+
+    /// Shows step `frame`, moving the clock to its time
+    fn jump_to(&mut self, frame: usize) {
+        self.frame = frame.min(self.last_frame());
+        if let Some(step) = self.steps.get(self.frame) {
+            self.clock = step.time;
+        }
+    }
+
+    /// sum of rewards up to & including the current step
+    fn return_so_far(&self) -> f32 {
+        self.steps
+            .iter()
+            .take(self.current() + 1)
+            .map(|s| s.reward)
+            .sum()
+    }
+}
+
+/// Spawns the single scene being replayed & stops physics from moving it
+pub fn setup_replay(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    replay: Res<Replay>,
+) {
+    rapier_config.physics_pipeline_active = false;
+
+    let params = SceneParams {
+        num_balls: replay.header.ball_classes.len(),
+        ..Default::default()
+    };
+    setup_scene(
+        &mut commands,
+        replay.header.scene as usize,
+        params,
+        replay.header.seed,
+        Vec3::ZERO,
+        Teams::default(),
+        Some(&mut SceneAssets {
+            meshes: &mut meshes,
+            materials: &mut materials,
+        }),
+    );
+    commands.insert_resource(AmbientLight {
+        color: Color::srgb(0.3, 0.3, 0.3),
+        brightness: 25_000.0,
+    });
+
+    commands.spawn((
+        TextBundle::from_section("", TextStyle::default()).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..Default::default()
+        }),
+        ReplayOverlay,
+    ));
+}
+
+/// P | play/pause, LEFT/RIGHT | step a frame, UP/DOWN | speed,
+/// hold COMMA/PERIOD | scrub, HOME/END | jump to start/end
+pub fn replay_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    mut replay: ResMut<Replay>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyP) {
+        replay.playing = !replay.playing;
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        replay.speed = (replay.speed * 2.).min(16.);
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        replay.speed = (replay.speed / 2.).max(1. / 16.);
+    }
+
+    // frame stepping pauses playback
+    if keyboard_input.just_pressed(KeyCode::ArrowRight) {
+        replay.playing = false;
+        let frame = replay.frame + 1;
+        replay.jump_to(frame);
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowLeft) {
+        replay.playing = false;
+        let frame = replay.frame.saturating_sub(1);
+        replay.jump_to(frame);
+    }
+    // scrubbing runs through the recording at 4x real time
+    let mut scrub = 0.;
+    if keyboard_input.pressed(KeyCode::Period) {
+        scrub += 4. * time.delta_seconds();
+    }
+    if keyboard_input.pressed(KeyCode::Comma) {
+        scrub -= 4. * time.delta_seconds();
+    }
+    if scrub != 0. {
+        let clock = replay.clock + scrub;
+        replay.seek(clock);
+    }
+    if keyboard_input.just_pressed(KeyCode::Home) {
+        replay.jump_to(0);
+    }
+    if keyboard_input.just_pressed(KeyCode::End) {
+        let last = replay.last_frame();
+        replay.jump_to(last);
+    }
+
+    if replay.playing {
+        let clock = replay.clock + replay.speed * time.delta_seconds();
+        replay.seek(clock);
+        if replay.frame >= replay.last_frame() {
+            replay.playing = false;
+        }
+    }
+}
+
+/// Moves every ball to where it was at the current step
+pub fn drive_replay(
+    replay: Res<Replay>,
+    scene_query: Query<&BallGameScene>,
+    mut scene_balls: SceneBalls,
+) {
+    let Some(step) = replay.steps.get(replay.current()) else {
+        return;
+    };
+    for scene in scene_query.iter() {
+        scene_balls.place(
+            scene.lead().ball,
+            step.player.position,
+            step.player.velocity,
+        );
+        let balls = scene.game_balls.iter().zip(step.balls.iter());
+        for ((entity, state), class) in balls.zip(replay.header.ball_classes.iter()) {
+            scene_balls.set_class(*entity, *class);
+            scene_balls.place(*entity, state.position, state.velocity);
+        }
+    }
+}
+
+pub fn update_replay_overlay(
+    replay: Res<Replay>,
+    mut overlay_query: Query<&mut Text, With<ReplayOverlay>>,
+) {
+    let Some(step) = replay.steps.get(replay.current()) else {
+        return;
+    };
+    let (up, down, left, right) = step.action;
+    let pressed = [(up, "UP"), (down, "DOWN"), (left, "LEFT"), (right, "RIGHT")]
+        .iter()
+        .filter(|(p, _)| *p)
+        .map(|(_, name)| *name)
+        .collect::<Vec<&str>>();
+
+    for mut text in overlay_query.iter_mut() {
+        text.sections[0].value = format!(
+            "step {}/{}  t={:.2}s  speed x{}{}\naction: {}\nreward: {:.3}  return: {:.3}\n\nP play/pause  LEFT/RIGHT step  UP/DOWN speed  hold ,/. scrub  HOME/END jump",
+            replay.current(),
+            replay.steps.len().saturating_sub(1),
+            step.time,
+            replay.speed,
+            if replay.playing { "" } else { "  [paused]" },
+            if pressed.is_empty() { "-".to_string() } else { pressed.join(" + ") },
+            step.reward,
+            replay.return_so_far(),
+        );
+    }
+}
@@ -3,8 +3,7 @@ use bevy::prelude::*;
 use bevy::window::{Cursor, CursorGrabMode, PrimaryWindow};
 use bevy_rapier3d::prelude::*;
 
-use crate::features::ball::*;
-use crate::scenes::ball_game_scene::reset_scene;
+use crate::scenes::ball_game_scene::{BallGameScene, SceneBalls};
 
 #[derive(Resource)]
 pub struct Settings {
@@ -32,13 +31,8 @@ pub fn apply_system_inputs(
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mouse_motion_events: EventReader<MouseMotion>,
     mut q_windows: Query<&mut Window, With<PrimaryWindow>>,
-    mut param_set: ParamSet<(
-        Query<&mut Transform, With<CameraController>>,
-        ParamSet<(
-            Query<(&mut Velocity, &mut Transform), With<Ball>>,
-            Query<(&mut Velocity, &mut Transform), With<ControllableBall>>,
-        )>,
-    )>,
+    mut param_set: ParamSet<(Query<&mut Transform, With<CameraController>>, SceneBalls)>,
+    mut scene_query: Query<&mut BallGameScene>,
     debug_render_state: ResMut<DebugRenderContext>,
 ) {
     let mut primary_window = q_windows.single_mut();
@@ -53,7 +47,10 @@ pub fn apply_system_inputs(
     }
     // R |      reset scene
     if (&keyboard_input).just_pressed(KeyCode::KeyR) {
-        reset_scene(param_set.p1());
+        let mut scene_balls = param_set.p1();
+        for mut scene in scene_query.iter_mut() {
+            scene_balls.reset(&mut scene);
+        }
     }
 
     // move camera
@@ -0,0 +1,157 @@
+//! Two teams per scene playing against each other: the learner's team
+//! against a frozen earlier snapshot of the same policy. The opponent
+//! plays the right half of the arena seen mirrored, so to it the game
+//! looks just like it does to the learner
+
+use bevy::prelude::*;
+
+use crate::features::ball::BallTag;
+use crate::modeling::ModelResource;
+use crate::util::config::Config;
+use crate::util::events::PolicyUpdatedEvent;
+
+/// team trained by the run, the other is played by the `Opponent`
+pub const LEARNER_TEAM: usize = 0;
+
+/// What each team is scored on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VersusGoal {
+    /// each team sorts the balls of its own half, the learner's quadrants
+    /// are those with x < 0
+    Split,
+    /// the learner sorts every ball, the opponent is rewarded for stopping it
+    Disrupt,
+}
+
+#[derive(Resource, Debug)]
+pub struct Versus {
+    pub goal: VersusGoal,
+    /// rounds between refreshing the opponent from the learner
+    pub refresh_every: u64,
+    /// fixed opponent checkpoint, in place of snapshots of the learner
+    pub opponent: Option<String>,
+}
+
+impl Versus {
+    /// Reads `versus.goal` (`split` or `disrupt`), `versus.refresh_every`
+    /// and `versus.opponent`
+    pub fn from_config(config: &Config) -> Self {
+        let goal = match config.get("versus.goal", "split".to_string()).as_str() {
+            "split" => VersusGoal::Split,
+            "disrupt" => VersusGoal::Disrupt,
+            other => {
+                eprintln!("Config: unknown versus.goal {}, using split", other);
+                VersusGoal::Split
+            }
+        };
+        let opponent = config.get("versus.opponent", String::new());
+        Versus {
+            goal,
+            refresh_every: config.get("versus.refresh_every", 10u64).max(1),
+            opponent: (!opponent.is_empty()).then_some(opponent),
+        }
+    }
+
+    /// Whether a ball of `class` counts towards `team`'s reward
+    pub fn scores(&self, team: usize, class: BallTag) -> bool {
+        match self.goal {
+            VersusGoal::Split => class
+                .target_quadrant()
+                .is_some_and(|(x, _)| (x > 0) == (team != LEARNER_TEAM)),
+            VersusGoal::Disrupt => true,
+        }
+    }
+}
+
+/// Who plays one side of a scene
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Seat {
+    Learner,
+    /// a frozen policy, by index into `Opponent::pool`
+    Pool(usize),
+}
+
+/// Who plays each team of a scene, `home` being `LEARNER_TEAM`'s side
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matchup {
+    pub home: Seat,
+    pub away: Seat,
+}
+
+impl Default for Matchup {
+    fn default() -> Self {
+        Matchup {
+            home: Seat::Learner,
+            away: Seat::Pool(0),
+        }
+    }
+}
+
+impl Matchup {
+    /// Whether the learner plays either side, exhibition games between two
+    /// frozen policies don't count towards the learner's stats
+    pub fn has_learner(&self) -> bool {
+        self.home == Seat::Learner || self.away == Seat::Learner
+    }
+
+    pub fn seat(&self, team: usize) -> Seat {
+        if team == LEARNER_TEAM {
+            self.home
+        } else {
+            self.away
+        }
+    }
+}
+
+/// Frozen policies the learner's team plays against
+#[derive(Resource)]
+pub struct Opponent {
+    pub pool: Vec<ModelResource>,
+}
+
+/// Swaps left & right, turning a mirrored action back into the arena's
+pub fn mirror_action(action: (bool, bool, bool, bool)) -> (bool, bool, bool, bool) {
+    let (up, down, left, right) = action;
+    (up, down, right, left)
+}
+
+/// Loads the fixed opponent, or starts self-play against a copy of the
+/// learner as it is before training
+pub fn load_opponent(mut commands: Commands, model: Res<ModelResource>, versus: Res<Versus>) {
+    let opponent = match &versus.opponent {
+        Some(path) => {
+            println!("Versus: playing against {}", path);
+            ModelResource::new(path, model.config)
+        }
+        None => {
+            let mut opponent = ModelResource::new(&model.config.model_path(), model.config);
+            opponent
+                ._vs
+                .copy(&model._vs)
+                .expect("Failed to copy the policy into the opponent");
+            opponent
+        }
+    };
+    commands.insert_resource(Opponent {
+        pool: vec![opponent],
+    });
+}
+
+/// Every `versus.refresh_every` rounds the opponent becomes a snapshot
+/// of the learner as it is now
+pub fn refresh_opponent(
+    model: Res<ModelResource>,
+    versus: Res<Versus>,
+    mut opponent: ResMut<Opponent>,
+    mut policy_reader: EventReader<PolicyUpdatedEvent>,
+) {
+    for update in policy_reader.read() {
+        if versus.opponent.is_some() || (update.round + 1) % versus.refresh_every != 0 {
+            continue;
+        }
+        match opponent.pool[0]._vs.copy(&model._vs) {
+            Ok(()) => println!("Versus: opponent refreshed from round {}", update.round),
+            Err(e) => eprintln!("Failed to refresh the opponent: {}", e),
+        }
+    }
+}
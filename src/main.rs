@@ -1,76 +1,339 @@
 use std::env;
+use std::path::Path;
 use std::time::Duration;
 
-use balltrainer::util::events::SimulationEndedEvent;
+use balltrainer::util::episode::EpisodeConfig;
+use balltrainer::util::events::{EpisodeEndedEvent, PolicyUpdatedEvent, SimulationEndedEvent};
 use balltrainer::util::playdata::on_simulation_end;
 use balltrainer::util::resources::update_world_state;
 use balltrainer::util::resources::WorldState;
 use bevy::app::ScheduleRunnerPlugin;
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
+use bevy::hierarchy::HierarchyPlugin;
+use bevy::input::InputPlugin;
 use bevy::prelude::*;
+use bevy::render::mesh::MeshPlugin;
+use bevy::scene::ScenePlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy::transform::TransformPlugin;
 use bevy_rapier3d::prelude::*;
 
 use balltrainer::features::ball::*;
+use balltrainer::features::camera_focus::*;
+use balltrainer::features::expert::{assign_expert_scenes, ExpertScenes};
+use balltrainer::features::hud::*;
+use balltrainer::features::league::{
+    schedule_league_matches, score_league_matches, start_league, League,
+};
 use balltrainer::features::player_controllers::*;
+use balltrainer::features::policy_overlay::*;
+use balltrainer::features::replay::*;
 use balltrainer::features::system::*;
 use balltrainer::features::ui::*;
-use balltrainer::modeling::load_model;
+use balltrainer::features::versus::{load_opponent, refresh_opponent, Versus};
+use balltrainer::modeling::behavior_cloning::{pretrain_policy, Pretrain};
+use balltrainer::modeling::dagger::{dagger_update, start_dagger, Dagger};
+use balltrainer::modeling::distributed::{run_learner, RolloutWorker};
+use balltrainer::modeling::{load_model, save_checkpoint, PolicyConfig};
+use balltrainer::scenes::isolation::SceneIsolation;
 use balltrainer::scenes::BallGameScene;
 
+use balltrainer::util::config::Config;
+use balltrainer::util::curriculum::Curriculum;
+use balltrainer::util::eval::{
+    finish_eval_round, snapshot_completed_scenes, start_eval, Evaluation,
+};
 use balltrainer::util::logging::*;
+use balltrainer::util::metrics::Metrics;
 use balltrainer::util::monitoring::print_fps_system;
-use balltrainer::util::playdata::check_simulation_end;
-use balltrainer::util::resources::{ProgramInputs, SimulationTimer};
+use balltrainer::util::playdata::{check_scene_complete, check_simulation_end};
+use balltrainer::util::resources::{ProgramInputs, SimulationTimer, WorldSeed};
+use balltrainer::util::reward::RewardFn;
+use balltrainer::util::run::{RunDir, RunLog};
+/// game time each frame of a headless run advances by
+const HEADLESS_TIMESTEP: Duration = Duration::from_nanos(16_666_667);
+
+/// value following a flag, e.g. `--frame-stack 4`
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    args.get(i + 1).cloned()
+}
+
+/// positive count following a flag, or a message saying why it isn't one
+fn count_flag(args: &[String], flag: &str) -> Result<Option<usize>, String> {
+    let Some(value) = flag_value(args, flag) else {
+        return Ok(None);
+    };
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(Some(n)),
+        _ => Err(format!("{} takes a positive number, not `{}`", flag, value)),
+    }
+}
+
+/// Reports why the run can't start & exits with a usage error
+fn usage_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(2)
+}
+
 fn main() {
     // capture program inputs
     let args: Vec<String> = env::args().collect();
+    let league = args.contains(&"--league".to_string());
+    let eval = flag_value(&args, "--eval");
+    let worker_socket = flag_value(&args, "--worker");
+    let frame_stack = match count_flag(&args, "--frame-stack") {
+        Ok(k) => k.unwrap_or(1),
+        Err(e) => usage_error(e),
+    };
+    let players = match count_flag(&args, "--players") {
+        Ok(n) => n.unwrap_or(1),
+        Err(e) => usage_error(e),
+    };
     let program_inputs = ProgramInputs {
-        headless: args.contains(&"--headless".to_string()),
+        headless: eval.is_some()
+            || worker_socket.is_some()
+            || args.contains(&"--headless".to_string()),
         ai_control: args.contains(&"--ai-control".to_string()),
+        image_obs: args.contains(&"--image-obs".to_string()),
+        frame_stack,
+        recurrent: args.contains(&"--recurrent".to_string()),
+        record: args.contains(&"--record".to_string()),
+        export_npz: args.contains(&"--export-npz".to_string()),
+        tensorboard: args.contains(&"--tensorboard".to_string()),
+        demo: args.contains(&"--demo".to_string()),
+        dagger: args.contains(&"--dagger".to_string()),
+        players,
+        independent_policies: args.contains(&"--independent-policies".to_string()),
+        versus: league || args.contains(&"--versus".to_string()),
+        league,
+    };
+    if (program_inputs.players > 1 || program_inputs.versus) && program_inputs.image_obs {
+        usage_error(
+            "--players & --versus need vector observations, the image can't tell player balls apart",
+        );
+    }
+    let n_workers = match count_flag(&args, "--workers") {
+        Ok(n) => n,
+        Err(e) => usage_error(e),
+    };
+    if (n_workers.is_some() || worker_socket.is_some())
+        && (program_inputs.independent_policies
+            || program_inputs.dagger
+            || program_inputs.league
+            || eval.is_some()
+            || args.contains(&"--pretrain".to_string()))
+    {
+        usage_error("--workers trains one shared policy with the learner's `learn`, it can't be combined with --independent-policies, --dagger, --league, --eval or --pretrain");
+    }
+    let mut config = match flag_value(&args, "--config") {
+        Some(path) => Config::load(&path).expect("Failed to load config"),
+        None => Config::default(),
     };
 
+    // learner of a multi-process run, the workers it starts play the game
+    if let Some(n_workers) = n_workers {
+        let run_dir = RunDir::create(&config).expect("Failed to create run directory");
+        run_dir
+            .write_config(&config, &args)
+            .expect("Failed to save config");
+        let policy = PolicyConfig::from_inputs(&program_inputs);
+        if let Err(e) = run_learner(n_workers, &args, policy, &run_dir) {
+            eprintln!("Learner failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let worker = worker_socket.map(|socket| {
+        // worker runs go under the learner's run directory
+        if let Some(learner_dir) = Path::new(&socket).parent() {
+            let dir = learner_dir.join("workers");
+            config.set("run.dir", &dir.to_string_lossy());
+        }
+        // the workers already fill every core, one torch thread each keeps
+        // them from fighting over them
+        tch::set_num_threads(1);
+        RolloutWorker::connect(&socket).expect("Failed to connect to the learner")
+    });
+    let mut world_seed = config.get("seed", rand::random());
+    if let Some(worker) = &worker {
+        // so workers don't all play the same layouts
+        world_seed ^= (worker.index as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15);
+    }
+
     let mut app = App::new();
-    app.add_plugins(DefaultPlugins)
-        .add_plugins(RapierDebugRenderPlugin::default())
-        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
+    if program_inputs.headless {
+        // training only: no window, renderer, meshes or materials, and each
+        // frame steps the game a fixed 1/60s as fast as it can run
+        app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::ZERO)))
+            .add_plugins((TransformPlugin, HierarchyPlugin, InputPlugin))
+            // rapier's async colliders look meshes & scenes up
+            .add_plugins((AssetPlugin::default(), MeshPlugin, ScenePlugin))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(HEADLESS_TIMESTEP));
+    } else {
+        app.add_plugins(DefaultPlugins)
+            .add_plugins(RapierDebugRenderPlugin::default())
+            .add_systems(Startup, setup_graphics)
+            .add_systems(Startup, setup_ui)
+            .add_systems(Startup, start_cursor_toggle_grab);
+    }
+    app.add_plugins(RapierPhysicsPlugin::<SceneIsolation>::default())
         .add_plugins(FrameTimeDiagnosticsPlugin::default()) // monitor fps
         //add events
         .add_event::<SimulationEndedEvent>()
+        .add_event::<EpisodeEndedEvent>()
+        .add_event::<PolicyUpdatedEvent>()
         // add resources
         // .insert_resource(Sett)
         .insert_resource(WorldState::new())
+        .insert_resource(RewardFn::from_config(&config))
+        .insert_resource(EpisodeConfig::from_config(&config))
+        .insert_resource(WorldSeed(world_seed))
         .insert_resource(SimulationTimer {
             timer: Timer::from_seconds(15.0, TimerMode::Repeating),
-        })
-        // startup systems
-        .add_systems(Startup, setup_graphics)
+        });
+
+    // replay setup: one scene driven by a recording, no physics or controller
+    if let Some(path) = flag_value(&args, "--replay") {
+        let replay = Replay::load(&path).expect("Failed to load recording");
+        app.insert_resource(replay)
+            .add_systems(Startup, setup_replay)
+            .add_systems(Update, apply_system_inputs)
+            .add_systems(Update, replay_controls)
+            .add_systems(Update, drive_replay.after(replay_controls))
+            .add_systems(Update, update_replay_overlay.after(replay_controls))
+            .insert_resource(program_inputs)
+            .insert_resource(config)
+            .run();
+        return;
+    }
+
+    let mut takeover = Takeover::from_config(&config);
+    if program_inputs.demo {
+        takeover.human_steps = HumanSteps::Demonstration;
+        app.add_systems(Startup, start_demo.after(BallGameScene::setup_world));
+    }
+    if let Some(expert) = ExpertScenes::from_config(&config) {
+        app.insert_resource(expert).add_systems(
+            Startup,
+            assign_expert_scenes.after(BallGameScene::setup_world),
+        );
+    }
+    if program_inputs.dagger {
+        app.insert_resource(Dagger::from_config(&config))
+            .add_systems(Startup, start_dagger.after(BallGameScene::setup_world))
+            .add_systems(Update, dagger_update.before(on_simulation_end));
+    }
+    if let Some(dir) = flag_value(&args, "--pretrain") {
+        app.insert_resource(Pretrain::from_config(dir, &config))
+            .add_systems(
+                Startup,
+                pretrain_policy.after(load_model).before(load_opponent),
+            );
+    }
+    if program_inputs.versus {
+        app.insert_resource(Versus::from_config(&config))
+            .add_systems(Startup, load_opponent.after(load_model));
+    }
+    if program_inputs.league {
+        let league = League::from_config(&config).expect("Failed to load league");
+        app.insert_resource(league)
+            .add_systems(
+                Startup,
+                start_league
+                    .after(load_opponent)
+                    .after(BallGameScene::setup_world),
+            )
+            .add_systems(Update, score_league_matches.before(on_simulation_end))
+            .add_systems(Update, schedule_league_matches.after(on_simulation_end));
+    } else if program_inputs.versus {
+        app.add_systems(Update, refresh_opponent.after(on_simulation_end));
+    }
+    app.insert_resource(takeover)
         .add_systems(Startup, BallGameScene::setup_world)
-        .add_systems(Startup, setup_ui)
-        .add_systems(Startup, start_cursor_toggle_grab)
         .add_systems(Startup, load_model)
-        // update systems
-        .add_systems(Update, print_fps_system)
         .add_systems(Update, apply_ball_drag)
         .add_systems(Update, check_simulation_end)
-        .add_systems(Update, on_simulation_end)
-        .add_systems(Update, update_world_state)
-        .add_systems(Update, move_balls);
+        .add_systems(Update, check_scene_complete.after(update_world_state))
+        .add_systems(Update, move_balls)
+        .add_systems(Update, update_world_state.after(move_balls));
+
+    // evaluation plays the suite in place of training
+    if let Some(policies) = eval {
+        let evaluation = match Evaluation::from_config(&policies, &config) {
+            Ok(evaluation) => evaluation,
+            Err(e) => usage_error(e),
+        };
+        app.insert_resource(evaluation)
+            .add_systems(
+                Startup,
+                start_eval
+                    .after(load_model)
+                    .after(BallGameScene::setup_world),
+            )
+            .add_systems(
+                Update,
+                snapshot_completed_scenes.after(check_scene_complete),
+            )
+            .add_systems(Update, finish_eval_round.after(snapshot_completed_scenes));
+    } else {
+        app.add_systems(Update, on_simulation_end);
+    }
 
     // headless setup
     if (&program_inputs).headless {
-        app.insert_resource(AggBallPositions::default())
+        app.insert_resource(AggBallPositions::from_config(&config))
             // .add_systems(Update, move_player_w_ai)
+            .add_systems(Update, print_fps_system)
             .add_systems(Update, track_ball_positions)
-            .add_plugins(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
-                1.0 / 60.0,
-            )));
+            .add_systems(Update, flush_ball_positions.before(on_simulation_end));
     // regular setup
     } else {
-        app.insert_resource(AggBallPositions::default())
-            .add_systems(Update, apply_system_inputs);
+        app.init_resource::<HudStats>()
+            .add_systems(Update, apply_system_inputs)
+            .add_systems(Startup, setup_hud.after(BallGameScene::setup_world))
+            .add_systems(Update, collect_hud_stats.after(on_simulation_end))
+            .add_systems(Update, sync_hud_visibility.after(apply_system_inputs))
+            .add_systems(Update, update_hud.after(collect_hud_stats))
+            .add_systems(Update, update_scene_labels)
+            .init_resource::<CameraFocus>()
+            .add_systems(Update, camera_focus_inputs.after(apply_system_inputs))
+            .add_systems(Update, update_camera_focus.after(camera_focus_inputs))
+            .add_systems(Update, toggle_takeover.after(camera_focus_inputs))
+            .add_systems(Update, toggle_policy_overlay)
+            .add_systems(Update, draw_policy_overlay.after(move_balls));
+    }
+
+    if program_inputs.record {
+        app.add_systems(Update, record_episode_steps.after(update_world_state));
     }
+    match Curriculum::from_config(&config) {
+        Ok(Some(curriculum)) => {
+            app.insert_resource(curriculum);
+        }
+        Ok(None) => {}
+        Err(e) => usage_error(e),
+    }
+
+    // run output, once every setting has been read
+    let run_dir = RunDir::create(&config).expect("Failed to create run directory");
+    if program_inputs.tensorboard {
+        app.insert_resource(Metrics::create(&run_dir).expect("Failed to create metrics log"));
+    }
+    run_dir
+        .write_config(&config, &args)
+        .expect("Failed to save config");
+    app.insert_resource(RunLog::create(&run_dir).expect("Failed to create run log"))
+        .insert_resource(run_dir)
+        .add_systems(Update, write_metrics.after(on_simulation_end));
+    // the learner keeps the checkpoints of multi-process runs
+    match worker {
+        Some(worker) => app.insert_resource(worker),
+        None => app.add_systems(Update, save_checkpoint.after(on_simulation_end)),
+    };
 
     // rest of general setup
     app.insert_resource(program_inputs);
+    app.insert_resource(config);
     app.run();
 }
@@ -0,0 +1,181 @@
+//! Supervised pretraining on human demonstrations before RL fine-tuning
+
+use bevy::prelude::*;
+use std::path::Path;
+use tch::{Kind, Reduction, TchError, Tensor};
+
+use super::{ModelResource, TeamPolicies, BPTT_LEN};
+use crate::util::config::Config;
+use crate::util::run::RunDir;
+
+/// Every demonstrated step, in the order they were played
+pub struct Demonstrations {
+    /// `[N, *observation]`
+    pub states: Tensor,
+    /// `[N, 4]`, 1 where the direction was pressed
+    pub actions: Tensor,
+    /// `[N]`, marks the last step of each episode
+    pub dones: Vec<bool>,
+}
+
+impl Demonstrations {
+    /// Reads every `.npz` in `dir` written with the export format,
+    /// e.g. a run's `demonstrations/`
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, TchError> {
+        let mut paths = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "npz"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let (mut states, mut actions, mut dones) = (Vec::new(), Vec::new(), Vec::new());
+        for path in paths.iter() {
+            let arrays = Tensor::read_npz(path)?;
+            let get = |name: &str| {
+                arrays
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, t)| t.shallow_clone())
+                    .ok_or_else(|| {
+                        TchError::FileFormat(format!("{} has no {}", path.display(), name))
+                    })
+            };
+            states.push(get("states")?.to_kind(Kind::Float));
+            actions.push(get("actions")?.to_kind(Kind::Float));
+            dones.extend(Vec::<bool>::try_from(get("dones")?)?);
+        }
+        if states.is_empty() {
+            return Err(TchError::FileFormat("no demonstrations found".to_string()));
+        }
+        println!(
+            "Loaded {} demonstrated steps from {} files",
+            dones.len(),
+            paths.len()
+        );
+        Ok(Demonstrations {
+            states: Tensor::cat(&states, 0),
+            actions: Tensor::cat(&actions, 0),
+            dones,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.dones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// BCE between the policy's logits & the demonstrated actions
+fn cloning_loss(logits: &Tensor, actions: &Tensor) -> Tensor {
+    logits.binary_cross_entropy_with_logits::<Tensor>(actions, None, None, Reduction::Mean)
+}
+
+/// Fits the policy to the demonstrations with a supervised loss, shuffled
+/// minibatches for feed-forward policies, in-order truncated BPTT for
+/// recurrent ones. Returns the mean loss of the last epoch
+pub fn behavior_cloning(
+    res: &mut ModelResource,
+    demos: &Demonstrations,
+    epochs: usize,
+    batch_size: usize,
+) -> f32 {
+    let n = demos.len() as i64;
+    let mut epoch_loss = 0.;
+    res.model.set_train();
+    for epoch in 0..epochs {
+        let mut losses = Vec::new();
+        if !res.config.recurrent {
+            let order = Tensor::randperm(n, (Kind::Int64, demos.states.device()));
+            for start in (0..n).step_by(batch_size.max(1)) {
+                let idx = order.narrow(0, start, (batch_size as i64).min(n - start));
+                let (logits, _) = res.forward(&demos.states.index_select(0, &idx), None, true);
+                let loss = cloning_loss(&logits, &demos.actions.index_select(0, &idx));
+                res.optimizer.backward_step(&loss);
+                losses.push(loss.double_value(&[]) as f32);
+            }
+        } else {
+            let mut h = res.initial_hidden(1);
+            for start in (0..n as usize).step_by(BPTT_LEN) {
+                let end = (start + BPTT_LEN).min(n as usize);
+                let mut logits = Vec::new();
+                for t in start..end {
+                    let s = demos.states.narrow(0, t as i64, 1);
+                    let (l, h_next) = res.forward(&s, Some(&h), true);
+                    logits.push(l);
+                    h = if demos.dones[t] {
+                        res.initial_hidden(1)
+                    } else {
+                        h_next.unwrap()
+                    };
+                }
+                let actions = demos.actions.narrow(0, start as i64, (end - start) as i64);
+                let loss = cloning_loss(&Tensor::cat(&logits, 0), &actions);
+                res.optimizer.backward_step(&loss);
+                losses.push(loss.double_value(&[]) as f32);
+                h = h.detach();
+            }
+        }
+        epoch_loss = losses.iter().sum::<f32>() / losses.len().max(1) as f32;
+        println!("Behavior cloning epoch {}: loss {:.4}", epoch, epoch_loss);
+    }
+    res.model.set_eval();
+    epoch_loss
+}
+
+/// Demonstrations to pretrain on, from `--pretrain <dir>`
+#[derive(Resource)]
+pub struct Pretrain {
+    pub dir: String,
+    pub epochs: usize,
+    pub batch_size: usize,
+}
+
+impl Pretrain {
+    pub fn from_config(dir: String, config: &Config) -> Self {
+        Pretrain {
+            dir,
+            epochs: config.get("bc.epochs", 10),
+            batch_size: config.get("bc.batch_size", 256),
+        }
+    }
+}
+
+/// Clones the demonstrations into the freshly loaded policy, and every
+/// teammate's own, saving them as the run's `checkpoints/pretrained.pt`
+/// & `pretrained_player<k>.pt`. Exits if there's nothing to clone
+pub fn pretrain_policy(
+    mut model: ResMut<ModelResource>,
+    mut team: Option<ResMut<TeamPolicies>>,
+    pretrain: Res<Pretrain>,
+    run_dir: Res<RunDir>,
+) {
+    let demos = match Demonstrations::load(&pretrain.dir) {
+        Ok(demos) => demos,
+        Err(e) => {
+            eprintln!("Can't pretrain on {}: {}", pretrain.dir, e);
+            std::process::exit(2)
+        }
+    };
+    behavior_cloning(&mut model, &demos, pretrain.epochs, pretrain.batch_size);
+    for model in team.iter_mut().flat_map(|team| team.models.iter_mut()) {
+        behavior_cloning(model, &demos, pretrain.epochs, pretrain.batch_size);
+    }
+
+    let models = TeamPolicies::with_lead(&model, team.as_deref());
+    for (k, model) in models.iter().enumerate() {
+        let name = match k {
+            0 => "checkpoints/pretrained.pt".to_string(),
+            k => format!("checkpoints/pretrained_player{}.pt", k),
+        };
+        let path = run_dir.join(name);
+        let result = std::fs::create_dir_all(run_dir.join("checkpoints"))
+            .map_err(TchError::from)
+            .and_then(|_| model.model.save(&path));
+        if let Err(e) = result {
+            eprintln!("Failed to save checkpoint {}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! DAgger: the policy drives, the scripted expert labels every state it
+//! visits, and the policy is refit to everything labelled so far
+
+use bevy::prelude::*;
+use tch::Tensor;
+
+use super::behavior_cloning::{behavior_cloning, Demonstrations};
+use super::{ModelResource, TeamPolicies, Trajectory};
+use crate::features::player_controllers::ControllerType;
+use crate::scenes::ball_game_scene::BallGameScene;
+use crate::util::config::Config;
+use crate::util::events::SimulationEndedEvent;
+
+/// Dataset aggregated over the DAgger iterations so far
+#[derive(Resource)]
+pub struct Dagger {
+    pub data: Option<Aggregate>,
+    /// chance the expert's action is taken instead of the policy's
+    pub beta: f64,
+    /// `beta` is multiplied by this after every iteration
+    pub beta_decay: f64,
+    /// oldest steps are dropped past this many
+    pub max_steps: usize,
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub iteration: u64,
+}
+
+impl Dagger {
+    pub fn from_config(config: &Config) -> Self {
+        Dagger {
+            data: None,
+            beta: config.get("dagger.beta", 0.0),
+            beta_decay: config.get("dagger.beta_decay", 0.5),
+            max_steps: config.get("dagger.max_steps", 200_000),
+            epochs: config.get("bc.epochs", 10),
+            batch_size: config.get("bc.batch_size", 256),
+            iteration: 0,
+        }
+    }
+
+    /// Adds an iteration's labelled steps, keeping at most `max_steps`
+    pub fn aggregate(&mut self, new: Demonstrations) {
+        self.data
+            .get_or_insert_with(|| Aggregate::like(&new))
+            .push(new, self.max_steps);
+    }
+}
+
+/// Labelled steps kept in tensors with room to spare, so an iteration's
+/// steps are copied in once instead of the whole dataset being rebuilt
+pub struct Aggregate {
+    states: Tensor,
+    actions: Tensor,
+    /// one per step written, the kept ones from `start` on
+    dones: Vec<bool>,
+    start: usize,
+}
+
+impl Aggregate {
+    /// Empty, for steps shaped like `demos`
+    fn like(demos: &Demonstrations) -> Self {
+        Aggregate {
+            states: with_capacity(&demos.states.narrow(0, 0, 0), 0),
+            actions: with_capacity(&demos.actions.narrow(0, 0, 0), 0),
+            dones: Vec::new(),
+            start: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.dones.len() - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends `new` after the kept steps, dropping the oldest past
+    /// `max_steps`. Once the tensors are full the kept steps move to the
+    /// front of new ones with room for as many again
+    fn push(&mut self, new: Demonstrations, max_steps: usize) {
+        let new = new.newest(max_steps);
+        let n = new.len();
+        let kept = self.len().min(max_steps - n);
+        self.start = self.dones.len() - kept;
+        if self.dones.len() + n > self.states.size()[0] as usize {
+            let (start, capacity) = (self.start as i64, 2 * (kept + n));
+            self.states = with_capacity(&self.states.narrow(0, start, kept as i64), capacity);
+            self.actions = with_capacity(&self.actions.narrow(0, start, kept as i64), capacity);
+            self.dones.drain(..self.start);
+            self.start = 0;
+        }
+        let end = self.dones.len() as i64;
+        self.states.narrow(0, end, n as i64).copy_(&new.states);
+        self.actions.narrow(0, end, n as i64).copy_(&new.actions);
+        self.dones.extend(new.dones);
+    }
+
+    /// The kept steps, oldest first, without copying the tensors
+    pub fn demonstrations(&self) -> Demonstrations {
+        let (start, len) = (self.start as i64, self.len() as i64);
+        Demonstrations {
+            states: self.states.narrow(0, start, len),
+            actions: self.actions.narrow(0, start, len),
+            dones: self.dones[self.start..].to_vec(),
+        }
+    }
+}
+
+/// `rows` at the front of a tensor with room for `capacity` of them
+fn with_capacity(rows: &Tensor, capacity: usize) -> Tensor {
+    let mut size = rows.size();
+    let n = size[0];
+    size[0] = capacity as i64;
+    let grown = Tensor::zeros(&size, (rows.kind(), rows.device()));
+    grown.narrow(0, 0, n).copy_(rows);
+    grown
+}
+
+impl Demonstrations {
+    /// Labelled steps of the given trajectories, each ending an episode.
+    /// `None` when there are none
+    pub fn from_trajectories(trajectories: &[&Trajectory]) -> Option<Self> {
+        let (mut states, mut actions, mut dones) = (Vec::new(), Vec::new(), Vec::new());
+        for trajectory in trajectories.iter().filter(|t| !t.state.is_empty()) {
+            let n = trajectory.state.len();
+            states.push(Tensor::cat(&trajectory.state, 0));
+            actions.push(Tensor::from_slice(&trajectory.action[..n].concat()).view([n as i64, 4]));
+            dones.extend_from_slice(&trajectory.done[..n]);
+            *dones.last_mut().unwrap() = true;
+        }
+        if states.is_empty() {
+            return None;
+        }
+        Some(Demonstrations {
+            states: Tensor::cat(&states, 0),
+            actions: Tensor::cat(&actions, 0),
+            dones,
+        })
+    }
+
+    /// The last `n` steps
+    fn newest(self, n: usize) -> Self {
+        let skip = self.len().saturating_sub(n);
+        if skip == 0 {
+            return self;
+        }
+        let keep = (self.len() - skip) as i64;
+        Demonstrations {
+            states: self.states.narrow(0, skip as i64, keep),
+            actions: self.actions.narrow(0, skip as i64, keep),
+            dones: self.dones[skip..].to_vec(),
+        }
+    }
+}
+
+/// The policy takes over every scene but stops learning from its rewards,
+/// it's trained on the expert's labels instead
+pub fn start_dagger(mut scene_query: Query<&mut BallGameScene>) {
+    for mut scene in scene_query.iter_mut() {
+        if scene.controller.is_training() {
+            scene.controller = ControllerType::AI { training: false };
+        }
+    }
+    println!("DAgger: the policy plays, the expert labels its states");
+}
+
+/// Aggregates the round's labelled states & refits the policy to all of
+/// them. Runs before `on_simulation_end` clears the scenes' demonstrations
+pub fn dagger_update(
+    mut event_reader: EventReader<SimulationEndedEvent>,
+    mut dagger: ResMut<Dagger>,
+    mut model: ResMut<ModelResource>,
+    mut team: Option<ResMut<TeamPolicies>>,
+    scene_query: Query<&BallGameScene>,
+) {
+    if event_reader.read().count() == 0 {
+        return;
+    }
+    let labelled = scene_query
+        .iter()
+        .flat_map(|scene| scene.players.iter())
+        .map(|player| &player.demonstration)
+        .collect::<Vec<_>>();
+    let Some(new) = Demonstrations::from_trajectories(&labelled) else {
+        return;
+    };
+    dagger.aggregate(new);
+
+    // the expert's labels don't depend on which player is asking, so the
+    // lead is refit once & its weights are copied into every teammate's
+    let dagger = &mut *dagger;
+    if let Some(data) = &dagger.data {
+        let data = data.demonstrations();
+        let loss = behavior_cloning(&mut model, &data, dagger.epochs, dagger.batch_size);
+        println!(
+            "DAgger iteration {}: {} labelled steps, loss {:.4}, beta {:.3}",
+            dagger.iteration,
+            data.len(),
+            loss,
+            dagger.beta
+        );
+        for (k, teammate) in team
+            .iter_mut()
+            .flat_map(|team| team.models.iter_mut())
+            .enumerate()
+        {
+            if let Err(e) = teammate._vs.copy(&model._vs) {
+                eprintln!("Failed to copy the refit policy to player {}: {}", k + 1, e);
+            }
+        }
+    }
+    dagger.iteration += 1;
+    dagger.beta *= dagger.beta_decay;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One step per value, each its own episode
+    fn steps(values: &[f32]) -> Demonstrations {
+        let n = values.len() as i64;
+        Demonstrations {
+            states: Tensor::from_slice(values).view([n, 1]),
+            actions: Tensor::from_slice(values).view([n, 1]).repeat([1, 4]),
+            dones: vec![true; values.len()],
+        }
+    }
+
+    fn states(aggregate: &Aggregate) -> Vec<f32> {
+        Vec::<f32>::try_from(aggregate.demonstrations().states.view([-1])).unwrap()
+    }
+
+    #[test]
+    fn keeps_the_newest_steps_in_order() {
+        let mut aggregate = Aggregate::like(&steps(&[0.]));
+        aggregate.push(steps(&[1., 2.]), 5);
+        aggregate.push(steps(&[3., 4., 5.]), 5);
+        assert_eq!(states(&aggregate), vec![1., 2., 3., 4., 5.]);
+        aggregate.push(steps(&[6.]), 5);
+        assert_eq!(states(&aggregate), vec![2., 3., 4., 5., 6.]);
+        aggregate.push(steps(&[7., 8., 9., 10., 11., 12.]), 5);
+        assert_eq!(states(&aggregate), vec![8., 9., 10., 11., 12.]);
+        assert_eq!(aggregate.demonstrations().dones.len(), 5);
+        let actions = aggregate.demonstrations().actions;
+        assert_eq!(actions.size(), vec![5, 4]);
+        assert_eq!(actions.double_value(&[0, 3]), 8.);
+    }
+
+    #[test]
+    fn only_grows_once_full() {
+        let mut aggregate = Aggregate::like(&steps(&[0.]));
+        aggregate.push(steps(&[1., 2.]), 100);
+        let capacity = aggregate.states.size()[0];
+        aggregate.push(steps(&[3., 4.]), 100);
+        assert_eq!(aggregate.states.size()[0], capacity);
+        assert_eq!(states(&aggregate), vec![1., 2., 3., 4.]);
+    }
+}
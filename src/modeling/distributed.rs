@@ -0,0 +1,459 @@
+//! Rollouts from several headless worker processes, learning in one
+//!
+//! The learner (`--workers <n>`) binds `learner.sock` in its run directory,
+//! starts `n` copies of the program with `--worker <socket>` and greets
+//! each with its index. After every round a worker sends its trajectories
+//! and waits; once every worker's are in, the learner runs `learn` on all
+//! of them and sends back the training stats & the updated weights.
+//!
+//! Messages are a little-endian `u64` payload length then the payload:
+//!
+//! ```text
+//! hello:    index u32
+//! rollout:  n_trajectories u32, then for each
+//!           n_steps u32 | n_dims u32 | observation dims [u32; n_dims]
+//!           | states [f32; n_steps * observation size]
+//!           | actions [u8; n_steps] (up=1, down=2, left=4, right=8)
+//!           | rewards [f32; n_steps] | dones [u8; n_steps] | human [u8; n_steps]
+//!           | hidden_len u32 | initial hidden [f32; hidden_len]
+//! update:   policy_loss f32 | entropy f32 | value_loss f32 (NaN for none)
+//!           | grad_norm f32 | learning_rate f64 | steps u64
+//!           | weights (the rest, a saved `VarStore`)
+//! ```
+
+use bevy::prelude::*;
+use std::io::{self, Cursor, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+use tch::Tensor;
+
+use super::{learn, ModelResource, PolicyConfig, TrainStats, Trajectory};
+use crate::util::run::{RunDir, RunLog};
+
+fn invalid(msg: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn send(stream: &mut UnixStream, payload: &[u8]) -> io::Result<()> {
+    stream.write_all(&(payload.len() as u64).to_le_bytes())?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+fn receive(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 8];
+    stream.read_exact(&mut len)?;
+    let mut payload = vec![0u8; u64::from_le_bytes(len) as usize];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: usize) {
+    buf.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn put_f32s(buf: &mut Vec<u8>, values: impl IntoIterator<Item = f32>) {
+    for value in values {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn get<const N: usize>(cursor: &mut Cursor<&[u8]>) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    cursor.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn get_u32(cursor: &mut Cursor<&[u8]>) -> io::Result<usize> {
+    Ok(u32::from_le_bytes(get(cursor)?) as usize)
+}
+
+fn get_f32s(cursor: &mut Cursor<&[u8]>, n: usize) -> io::Result<Vec<f32>> {
+    (0..n)
+        .map(|_| Ok(f32::from_le_bytes(get(cursor)?)))
+        .collect()
+}
+
+fn get_bytes(cursor: &mut Cursor<&[u8]>, n: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0u8; n];
+    cursor.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn tensor_values(tensor: &Tensor) -> io::Result<Vec<f32>> {
+    Vec::<f32>::try_from(tensor.flatten(0, -1)).map_err(invalid)
+}
+
+fn encode_rollout(trajectories: &[&Trajectory]) -> io::Result<Vec<u8>> {
+    let trajectories = trajectories
+        .iter()
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>();
+    let mut buf = Vec::new();
+    put_u32(&mut buf, trajectories.len());
+    for trajectory in trajectories {
+        let n = trajectory.len();
+        let states = Tensor::cat(&trajectory.state[..n], 0);
+        let dims = states.size()[1..].to_vec();
+        put_u32(&mut buf, n);
+        put_u32(&mut buf, dims.len());
+        for dim in dims.iter() {
+            put_u32(&mut buf, *dim as usize);
+        }
+        put_f32s(&mut buf, tensor_values(&states)?);
+        buf.extend(trajectory.action[..n].iter().map(|action| {
+            action
+                .iter()
+                .enumerate()
+                .fold(0u8, |bits, (i, a)| bits | (((*a > 0.5) as u8) << i))
+        }));
+        put_f32s(&mut buf, trajectory.reward[..n].iter().copied());
+        buf.extend(trajectory.done[..n].iter().map(|d| *d as u8));
+        buf.extend(trajectory.human[..n].iter().map(|h| *h as u8));
+        let hidden = match &trajectory.initial_hidden {
+            Some(h) => tensor_values(h)?,
+            None => Vec::new(),
+        };
+        put_u32(&mut buf, hidden.len());
+        put_f32s(&mut buf, hidden);
+    }
+    Ok(buf)
+}
+
+fn decode_rollout(payload: &[u8]) -> io::Result<Vec<Trajectory>> {
+    let mut cursor = Cursor::new(payload);
+    let n_trajectories = get_u32(&mut cursor)?;
+    let mut trajectories = Vec::with_capacity(n_trajectories);
+    for _ in 0..n_trajectories {
+        let n = get_u32(&mut cursor)?;
+        let n_dims = get_u32(&mut cursor)?;
+        let mut shape = vec![n as i64];
+        for _ in 0..n_dims {
+            shape.push(get_u32(&mut cursor)? as i64);
+        }
+        let size = shape.iter().product::<i64>() as usize;
+        let states = Tensor::from_slice(&get_f32s(&mut cursor, size)?).view(shape.as_slice());
+
+        let mut trajectory = Trajectory::new();
+        trajectory.state = states.split(1, 0);
+        trajectory.action = get_bytes(&mut cursor, n)?
+            .into_iter()
+            .map(|bits| std::array::from_fn(|i| ((bits >> i) & 1) as f32))
+            .collect();
+        trajectory.reward = get_f32s(&mut cursor, n)?;
+        trajectory.done = get_bytes(&mut cursor, n)?.iter().map(|d| *d != 0).collect();
+        trajectory.human = get_bytes(&mut cursor, n)?.iter().map(|h| *h != 0).collect();
+        let hidden_len = get_u32(&mut cursor)?;
+        if hidden_len > 0 {
+            let hidden = get_f32s(&mut cursor, hidden_len)?;
+            trajectory.initial_hidden = Some(Tensor::from_slice(&hidden).view([1, -1]));
+        }
+        trajectories.push(trajectory);
+    }
+    Ok(trajectories)
+}
+
+fn put_stats(buf: &mut Vec<u8>, stats: &TrainStats) {
+    put_f32s(
+        buf,
+        [
+            stats.policy_loss,
+            stats.entropy,
+            stats.value_loss.unwrap_or(f32::NAN),
+            stats.grad_norm,
+        ],
+    );
+    buf.extend_from_slice(&stats.learning_rate.to_le_bytes());
+    buf.extend_from_slice(&(stats.steps as u64).to_le_bytes());
+}
+
+fn get_stats(cursor: &mut Cursor<&[u8]>) -> io::Result<TrainStats> {
+    let values = get_f32s(cursor, 4)?;
+    let mut stats = TrainStats::default();
+    stats.policy_loss = values[0];
+    stats.entropy = values[1];
+    stats.value_loss = (!values[2].is_nan()).then_some(values[2]);
+    stats.grad_norm = values[3];
+    stats.learning_rate = f64::from_le_bytes(get(cursor)?);
+    stats.steps = u64::from_le_bytes(get(cursor)?) as usize;
+    Ok(stats)
+}
+
+fn encode_update(stats: &TrainStats, model: &ModelResource) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    put_stats(&mut buf, stats);
+    model._vs.save_to_stream(&mut buf).map_err(invalid)?;
+    Ok(buf)
+}
+
+/// Loads the weights into `model`, returning the stats they came with
+fn decode_update(payload: &[u8], model: &mut ModelResource) -> io::Result<TrainStats> {
+    let mut cursor = Cursor::new(payload);
+    let stats = get_stats(&mut cursor)?;
+    let weights = &payload[cursor.position() as usize..];
+    model
+        ._vs
+        .load_from_stream(Cursor::new(weights))
+        .map_err(invalid)?;
+    Ok(stats)
+}
+
+/// A worker's connection to the learner
+#[derive(Resource)]
+pub struct RolloutWorker {
+    stream: UnixStream,
+    /// which of the learner's workers this is
+    pub index: usize,
+}
+
+impl RolloutWorker {
+    /// Connects to the learner & waits to be told the worker's index
+    pub fn connect(socket: impl AsRef<Path>) -> io::Result<Self> {
+        let mut stream = UnixStream::connect(socket)?;
+        let hello = receive(&mut stream)?;
+        let index = get_u32(&mut Cursor::new(hello.as_slice()))?;
+        println!("Worker {}: connected to the learner", index);
+        Ok(RolloutWorker { stream, index })
+    }
+
+    /// Sends the round's trajectories & takes on the weights the learner
+    /// trained on them, in place of learning locally
+    pub fn exchange(
+        &mut self,
+        model: &mut ModelResource,
+        trajectories: Vec<&Trajectory>,
+    ) -> io::Result<TrainStats> {
+        send(&mut self.stream, &encode_rollout(&trajectories)?)?;
+        let update = receive(&mut self.stream)?;
+        decode_update(&update, model)
+    }
+}
+
+/// Starts `n_workers` copies of this program as workers, passing on the
+/// command line the learner was started with
+fn spawn_workers(n_workers: usize, args: &[String], socket: &Path) -> io::Result<Vec<Child>> {
+    let exe = std::env::current_exe()?;
+    let mut passthrough = Vec::new();
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--workers" {
+            args.next();
+        } else {
+            passthrough.push(arg.clone());
+        }
+    }
+    (0..n_workers)
+        .map(|_| {
+            Command::new(&exe)
+                .args(&passthrough)
+                .arg("--worker")
+                .arg(socket)
+                .spawn()
+        })
+        .collect()
+}
+
+/// Greets each worker as it connects. Fails, stopping the rest, if a
+/// worker exits before connecting, e.g. on a bad flag or missing model
+fn accept_workers(listener: &UnixListener, children: &mut [Child]) -> io::Result<Vec<UnixStream>> {
+    listener.set_nonblocking(true)?;
+    let mut workers = Vec::new();
+    while workers.len() < children.len() {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                stream.set_nonblocking(false)?;
+                let mut hello = Vec::new();
+                put_u32(&mut hello, workers.len());
+                send(&mut stream, &hello)?;
+                workers.push(stream);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                for (index, child) in children.iter_mut().enumerate() {
+                    if let Some(status) = child.try_wait()? {
+                        for child in children.iter_mut() {
+                            let _ = child.kill();
+                            let _ = child.wait();
+                        }
+                        return Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("worker {} exited before connecting ({})", index, status),
+                        ));
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(workers)
+}
+
+/// Runs the learner until a worker goes away: every round gathers each
+/// worker's trajectories, learns on them & sends the weights back
+pub fn run_learner(
+    n_workers: usize,
+    args: &[String],
+    policy: PolicyConfig,
+    run_dir: &RunDir,
+) -> io::Result<()> {
+    let socket = run_dir.join("learner.sock");
+    let listener = UnixListener::bind(&socket)?;
+    let mut children = spawn_workers(n_workers, args, &socket)?;
+    let mut workers = match accept_workers(&listener, &mut children) {
+        Ok(workers) => workers,
+        Err(e) => {
+            let _ = std::fs::remove_file(&socket);
+            return Err(e);
+        }
+    };
+    println!("Learner: {} workers connected", n_workers);
+
+    let mut model = ModelResource::new(&policy.model_path(), policy);
+    let mut run_log = RunLog::create(run_dir)?;
+    let mut round = 0;
+    let error = loop {
+        let result = learner_round(round, &mut workers, &mut model, &mut run_log, run_dir);
+        if let Err(e) = result {
+            break e;
+        }
+        round += 1;
+    };
+
+    // workers see the connection close & exit
+    drop(workers);
+    for child in children.iter_mut() {
+        child.wait()?;
+    }
+    let _ = std::fs::remove_file(&socket);
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+        println!("Learner: a worker stopped, shutting down");
+        return Ok(());
+    }
+    Err(error)
+}
+
+fn learner_round(
+    round: u64,
+    workers: &mut [UnixStream],
+    model: &mut ModelResource,
+    run_log: &mut RunLog,
+    run_dir: &RunDir,
+) -> io::Result<()> {
+    let start = Instant::now();
+    let mut trajectories = Vec::new();
+    for stream in workers.iter_mut() {
+        trajectories.extend(decode_rollout(&receive(stream)?)?);
+    }
+    let stats = learn(model, trajectories.iter().collect());
+    let update = encode_update(&stats, model)?;
+    for stream in workers.iter_mut() {
+        send(stream, &update)?;
+    }
+    println!(
+        "Learner round {}: {} trajectories, {} steps, policy loss {:.4}",
+        round,
+        trajectories.len(),
+        stats.steps,
+        stats.policy_loss
+    );
+
+    let steps_per_second = stats.steps as f32 / start.elapsed().as_secs_f32().max(1e-6);
+    run_log.update(round, &stats, steps_per_second)?;
+    run_log.flush()?;
+    if (round + 1) % run_dir.checkpoint_every == 0 {
+        std::fs::create_dir_all(run_dir.join("checkpoints"))?;
+        let path = run_dir.join(format!("checkpoints/round{:05}.pt", round));
+        model.model.save(&path).map_err(invalid)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trajectory(shape: &[i64], n: usize, hidden: Option<Tensor>) -> Trajectory {
+        let mut trajectory = Trajectory::new();
+        for t in 0..n {
+            let size = shape.iter().product::<i64>();
+            let state = (Tensor::arange(size, (tch::Kind::Float, tch::Device::Cpu))
+                + t as f64 * 100.)
+                .view(shape);
+            let bits = (t % 16) as u8;
+            let action = std::array::from_fn(|i| ((bits >> i) & 1) as f32);
+            if t % 3 == 1 {
+                trajectory.push_human_step(state, action);
+            } else {
+                trajectory.push_step(state, action);
+            }
+            trajectory.reward.push(t as f32 * 0.5 - 1.);
+        }
+        if let Some(done) = trajectory.done.last_mut() {
+            *done = true;
+        }
+        trajectory.initial_hidden = hidden;
+        trajectory
+    }
+
+    fn assert_same(a: &Trajectory, b: &Trajectory) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.state.iter().zip(b.state.iter()) {
+            assert_eq!(x.size(), y.size());
+            assert!(x.equal(y));
+        }
+        assert_eq!(a.action, b.action);
+        assert_eq!(a.reward, b.reward);
+        assert_eq!(a.done, b.done);
+        assert_eq!(a.human, b.human);
+        match (&a.initial_hidden, &b.initial_hidden) {
+            (None, None) => {}
+            (Some(x), Some(y)) => assert!(x.equal(y)),
+            _ => panic!("initial hidden state didn't round-trip"),
+        }
+    }
+
+    #[test]
+    fn rollout_round_trips() {
+        let hidden = Tensor::from_slice(&[0.25f32, -1.5, 3.0]).view([1, -1]);
+        let image = trajectory(&[1, 4, 3, 2], 20, Some(hidden));
+        let vector = trajectory(&[1, 7], 5, None);
+        let empty = Trajectory::new();
+
+        let payload = encode_rollout(&[&image, &empty, &vector]).unwrap();
+        let decoded = decode_rollout(&payload).unwrap();
+        // empty trajectories aren't sent
+        assert_eq!(decoded.len(), 2);
+        assert_same(&image, &decoded[0]);
+        assert_same(&vector, &decoded[1]);
+    }
+
+    #[test]
+    fn rollout_rejects_a_truncated_payload() {
+        let payload = encode_rollout(&[&trajectory(&[1, 7], 5, None)]).unwrap();
+        assert!(decode_rollout(&payload[..payload.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn update_stats_round_trip() {
+        for value_loss in [None, Some(0.75)] {
+            let mut stats = TrainStats::default();
+            stats.policy_loss = -0.125;
+            stats.entropy = 1.25;
+            stats.value_loss = value_loss;
+            stats.grad_norm = 3.5;
+            stats.learning_rate = 1e-4;
+            stats.steps = 12345;
+            let mut buf = Vec::new();
+            put_stats(&mut buf, &stats);
+            let decoded = get_stats(&mut Cursor::new(buf.as_slice())).unwrap();
+            assert_eq!(decoded.policy_loss, stats.policy_loss);
+            assert_eq!(decoded.entropy, stats.entropy);
+            assert_eq!(decoded.value_loss, stats.value_loss);
+            assert_eq!(decoded.grad_norm, stats.grad_norm);
+            assert_eq!(decoded.learning_rate, stats.learning_rate);
+            assert_eq!(decoded.steps, stats.steps);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Dumps collected trajectories to `.npz` for analysis in Python
+//!
+//! With `T` steps over all scenes & `S` (scene, player) trajectories the archive holds:
+//!
+//! | array          | dtype   | shape                  |
+//! |----------------|---------|------------------------|
+//! | `states`       | float32 | `(T, *observation)`    |
+//! | `actions`      | bool    | `(T, 4)` up/down/left/right |
+//! | `rewards`      | float32 | `(T,)`                 |
+//! | `dones`        | bool    | `(T,)`                 |
+//! | `human`        | bool    | `(T,)` steps a human took over |
+//! | `scene`        | int64   | `(T,)` scene id of each step |
+//! | `player`       | int64   | `(T,)` which of the scene's players took the step |
+//! | `scene_ids`    | int64   | `(S,)`                 |
+//! | `ball_classes` | uint8   | `(S, MAX_BALLS)`, `255` for balls not in play |
+//!
+//! Classes are 0 red, 1 blue, 2 green, 3 yellow, in the order balls
+//! appear in the observation.
+
+use std::io;
+use std::path::Path;
+
+use super::Trajectory;
+use crate::features::ball::BallTag;
+use crate::scenes::ball_game_scene::MAX_BALLS;
+use crate::util::npy::*;
+
+/// marks a ball slot that isn't in play
+const NO_BALL: u8 = 255;
+
+/// One player's trajectory & the classes of its scene's game balls
+pub struct SceneTrajectory<'a> {
+    pub scene: usize,
+    pub player: usize,
+    pub trajectory: &'a Trajectory,
+    pub ball_classes: Vec<BallTag>,
+}
+
+pub fn export_trajectories(path: impl AsRef<Path>, scenes: &[SceneTrajectory]) -> io::Result<()> {
+    let mut obs_shape = Vec::new();
+    let (mut states, mut actions, mut rewards, mut dones, mut human) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let (mut scene_of_step, mut player_of_step) = (Vec::new(), Vec::new());
+    for scene in scenes {
+        let trajectory = scene.trajectory;
+        for t in 0..trajectory.len() {
+            let state = &trajectory.state[t];
+            // drop the batch dimension every state is stored with
+            let shape = state.size()[1..]
+                .iter()
+                .map(|d| *d as usize)
+                .collect::<Vec<usize>>();
+            if obs_shape.is_empty() {
+                obs_shape = shape;
+            } else if obs_shape != shape {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "trajectories have differently shaped observations",
+                ));
+            }
+            let values = Vec::<f32>::try_from(state.flatten(0, -1))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            states.extend(values);
+            actions.extend(trajectory.action[t].iter().map(|a| *a > 0.5));
+            rewards.push(trajectory.reward[t]);
+            dones.push(trajectory.done[t]);
+            human.push(trajectory.human[t]);
+            scene_of_step.push(scene.scene as i64);
+            player_of_step.push(scene.player as i64);
+        }
+    }
+
+    let n_steps = rewards.len();
+    let mut ball_classes = Vec::new();
+    for scene in scenes {
+        let mut row = scene
+            .ball_classes
+            .iter()
+            .take(MAX_BALLS)
+            .map(|c| c.to_u8())
+            .collect::<Vec<u8>>();
+        row.resize(MAX_BALLS, NO_BALL);
+        ball_classes.extend(row);
+    }
+    let scene_ids = scenes.iter().map(|s| s.scene as i64).collect::<Vec<i64>>();
+
+    let mut npz = NpzWriter::create(path)?;
+    let mut states_shape = vec![n_steps];
+    states_shape.extend(obs_shape);
+    npz.add("states", &NpyArray::new(states_shape, NpyData::F32(states)))?;
+    npz.add(
+        "actions",
+        &NpyArray::new(vec![n_steps, 4], NpyData::Bool(actions)),
+    )?;
+    npz.add(
+        "rewards",
+        &NpyArray::new(vec![n_steps], NpyData::F32(rewards)),
+    )?;
+    npz.add("dones", &NpyArray::new(vec![n_steps], NpyData::Bool(dones)))?;
+    npz.add("human", &NpyArray::new(vec![n_steps], NpyData::Bool(human)))?;
+    npz.add(
+        "scene",
+        &NpyArray::new(vec![n_steps], NpyData::I64(scene_of_step)),
+    )?;
+    npz.add(
+        "player",
+        &NpyArray::new(vec![n_steps], NpyData::I64(player_of_step)),
+    )?;
+    npz.add(
+        "scene_ids",
+        &NpyArray::new(vec![scenes.len()], NpyData::I64(scene_ids)),
+    )?;
+    npz.add(
+        "ball_classes",
+        &NpyArray::new(vec![scenes.len(), MAX_BALLS], NpyData::U8(ball_classes)),
+    )?;
+    npz.finish()
+}
@@ -1,12 +1,24 @@
 use bevy::prelude::*;
+use tch::nn::ModuleT;
 use tch::*;
 
+use super::observation::ObservationType;
+use crate::util::events::PolicyUpdatedEvent;
+use crate::util::resources::ProgramInputs;
+use crate::util::run::RunDir;
+
 #[derive(Component)]
 pub struct Trajectory {
     // pub steps: Vec<(Tensor, f32, f32)>,
     pub state: Vec<Tensor>,
-    pub action: Vec<f32>,
+    pub action: Vec<[f32; 4]>,
     pub reward: Vec<f32>,
+    /// marks the last step of an episode
+    pub done: Vec<bool>,
+    /// marks steps a human took over from the policy
+    pub human: Vec<bool>,
+    /// recurrent state the policy had before the first step
+    pub initial_hidden: Option<Tensor>,
 }
 
 // * i think we do `unsafe` thing bc
@@ -18,25 +30,217 @@ impl Trajectory {
             state: Vec::new(),
             action: Vec::new(),
             reward: Vec::new(),
+            done: Vec::new(),
+            human: Vec::new(),
+            initial_hidden: None,
         }
     }
+
+    /// number of complete (state, action, reward) steps
+    pub fn len(&self) -> usize {
+        self.state
+            .len()
+            .min(self.action.len())
+            .min(self.reward.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push_step(&mut self, state: Tensor, action: [f32; 4]) {
+        self.state.push(state);
+        self.action.push(action);
+        self.done.push(false);
+        self.human.push(false);
+    }
+
+    /// Adds a step taken by a human in place of the policy
+    pub fn push_human_step(&mut self, state: Tensor, action: [f32; 4]) {
+        self.push_step(state, action);
+        *self.human.last_mut().unwrap() = true;
+    }
+
+    /// Gives the newest step its reward, if it doesn't have one yet
+    pub fn reward_pending(&mut self, reward: f32) {
+        if self.reward.len() < self.state.len() {
+            self.reward.push(reward);
+        }
+    }
+
+    /// Marks the most recent step as the end of an episode
+    pub fn end_episode(&mut self) {
+        if let Some(done) = self.done.last_mut() {
+            *done = true;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.state.clear();
+        self.action.clear();
+        self.reward.clear();
+        self.done.clear();
+        self.human.clear();
+        self.initial_hidden = None;
+    }
+}
+
+/// Describes the inputs & architecture the loaded policy expects
+#[derive(Clone, Copy, Debug)]
+pub struct PolicyConfig {
+    pub observation: ObservationType,
+    /// number of consecutive observations given to the policy
+    pub frame_stack: usize,
+    /// policy takes & returns a hidden state each step
+    pub recurrent: bool,
 }
 
+impl PolicyConfig {
+    /// The policy the command line asks for
+    pub fn from_inputs(program_inputs: &ProgramInputs) -> Self {
+        PolicyConfig {
+            observation: if program_inputs.image_obs {
+                ObservationType::Image
+            } else {
+                ObservationType::Vector
+            },
+            frame_stack: program_inputs.frame_stack,
+            recurrent: program_inputs.recurrent,
+        }
+    }
+
+    pub fn model_path(&self) -> String {
+        let mut name = String::from("src/modeling/ball_policy");
+        if self.observation == ObservationType::Image {
+            name.push_str("_cnn");
+        }
+        if self.recurrent {
+            name.push_str("_rnn");
+        }
+        name + ".pt"
+    }
+}
+
+pub const LEARNING_RATE: f64 = 1e-4;
+
 #[derive(Resource)]
 pub struct ModelResource {
     pub model: TrainableCModule,
     pub _vs: nn::VarStore,
+    pub optimizer: nn::Optimizer,
+    pub config: PolicyConfig,
 }
+
+// * optimizer is not `Sync`, see `Trajectory`
+unsafe impl Sync for ModelResource {}
 impl ModelResource {
-    pub fn new(model_path: &str) -> Self {
+    pub fn new(model_path: &str, config: PolicyConfig) -> Self {
         let vs = nn::VarStore::new(Device::Cpu);
         let mut model =
             TrainableCModule::load(model_path, vs.root()).expect("Failed to load model");
         model.set_eval();
-        ModelResource { model, _vs: vs }
+        let optimizer = nn::Adam::default()
+            .build(&vs, LEARNING_RATE)
+            .expect("Failed to build optimizer");
+        ModelResource {
+            model,
+            _vs: vs,
+            optimizer,
+            config,
+        }
+    }
+
+    /// Zeroed hidden state for a batch, as defined by the model itself
+    pub fn initial_hidden(&self, batch_size: i64) -> Tensor {
+        match self
+            .model
+            .method_is("initial_hidden", &[IValue::Int(batch_size)])
+        {
+            Ok(IValue::Tensor(h)) => h,
+            _ => panic!("recurrent policy must export `initial_hidden(batch_size)`"),
+        }
+    }
+
+    /// Runs the policy, returning action logits and the next hidden
+    /// state (recurrent policies only)
+    pub fn forward(&self, s: &Tensor, h: Option<&Tensor>, train: bool) -> (Tensor, Option<Tensor>) {
+        match h {
+            None => (self.model.forward_t(s, train), None),
+            Some(h) => {
+                let inputs = [
+                    IValue::Tensor(s.shallow_clone()),
+                    IValue::Tensor(h.shallow_clone()),
+                ];
+                match self.model.forward_is(&inputs) {
+                    Ok(IValue::Tuple(mut out)) if out.len() == 2 => {
+                        match (out.remove(0), out.remove(0)) {
+                            (IValue::Tensor(logits), IValue::Tensor(h)) => (logits, Some(h)),
+                            _ => panic!("recurrent policy must return (logits, hidden)"),
+                        }
+                    }
+                    _ => panic!("recurrent policy must return (logits, hidden)"),
+                }
+            }
+        }
+    }
+}
+
+/// Teammates' own policies with `--independent-policies`: player `k` is
+/// driven by `models[k - 1]`, the lead by `ModelResource`
+#[derive(Resource)]
+pub struct TeamPolicies {
+    pub models: Vec<ModelResource>,
+}
+
+impl TeamPolicies {
+    /// Every policy in player order, `lead` first
+    pub fn with_lead<'a>(
+        lead: &'a ModelResource,
+        team: Option<&'a TeamPolicies>,
+    ) -> Vec<&'a ModelResource> {
+        std::iter::once(lead)
+            .chain(team.iter().flat_map(|team| team.models.iter()))
+            .collect()
     }
 }
-pub fn load_model(mut commands: Commands) {
-    let model_resource = ModelResource::new("src/modeling/ball_policy.pt");
+
+pub fn load_model(mut commands: Commands, program_inputs: Res<ProgramInputs>) {
+    let config = PolicyConfig::from_inputs(&program_inputs);
+    let model_resource = ModelResource::new(&config.model_path(), config);
     commands.insert_resource(model_resource);
+    if program_inputs.independent_policies && program_inputs.players > 1 {
+        let models = (1..program_inputs.players)
+            .map(|_| ModelResource::new(&config.model_path(), config))
+            .collect();
+        commands.insert_resource(TeamPolicies { models });
+    }
+}
+
+/// Saves the policy to the run's `checkpoints/` every few rounds, teammates'
+/// own policies as `round<n>_player<k>.pt`
+pub fn save_checkpoint(
+    model: Res<ModelResource>,
+    team: Option<Res<TeamPolicies>>,
+    run_dir: Res<RunDir>,
+    mut policy_reader: EventReader<PolicyUpdatedEvent>,
+) {
+    for update in policy_reader.read() {
+        if (update.round + 1) % run_dir.checkpoint_every != 0 {
+            continue;
+        }
+        let models = TeamPolicies::with_lead(&model, team.as_deref());
+        for (k, model) in models.iter().enumerate() {
+            let name = match k {
+                0 => format!("checkpoints/round{:05}.pt", update.round),
+                k => format!("checkpoints/round{:05}_player{}.pt", update.round, k),
+            };
+            let path = run_dir.join(name);
+            let result = std::fs::create_dir_all(run_dir.join("checkpoints"))
+                .map_err(TchError::from)
+                .and_then(|_| model.model.save(&path));
+            if let Err(e) = result {
+                eprintln!("Failed to save checkpoint {}: {}", path.display(), e);
+            }
+        }
+    }
 }
@@ -0,0 +1,44 @@
+use std::collections::VecDeque;
+use tch::Tensor;
+
+/// What a policy remembers about its scene between steps:
+/// the last few observations for frame stacking, and the
+/// hidden state of a recurrent policy
+pub struct PolicyMemory {
+    frames: VecDeque<Tensor>,
+    pub hidden: Option<Tensor>,
+}
+
+// * see `Trajectory`
+unsafe impl Sync for PolicyMemory {}
+impl PolicyMemory {
+    pub fn new() -> Self {
+        PolicyMemory {
+            frames: VecDeque::new(),
+            hidden: None,
+        }
+    }
+
+    /// Adds the newest observation and returns the last `k` observations
+    /// concatenated along the feature/channel dimension (oldest first).
+    /// At the start of an episode the first observation is repeated.
+    pub fn stack(&mut self, observation: Tensor, k: usize) -> Tensor {
+        if k <= 1 {
+            return observation;
+        }
+        while self.frames.len() < k - 1 {
+            self.frames.push_back(observation.shallow_clone());
+        }
+        self.frames.push_back(observation);
+        while self.frames.len() > k {
+            self.frames.pop_front();
+        }
+        Tensor::cat(&self.frames.iter().collect::<Vec<_>>(), 1)
+    }
+
+    /// Forget everything, called on episode boundaries
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.hidden = None;
+    }
+}
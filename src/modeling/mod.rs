@@ -3,3 +3,11 @@ pub use general::*;
 
 pub mod train;
 pub use train::*;
+
+pub mod behavior_cloning;
+pub mod dagger;
+pub mod distributed;
+pub mod export;
+pub mod memory;
+pub mod observation;
+pub mod rasterizer;
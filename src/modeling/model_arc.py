@@ -1,4 +1,5 @@
-from typing import Iterable
+import argparse
+from typing import Iterable, Tuple
 import numpy as np
 import torch
 import torch.nn as nn
@@ -35,22 +36,25 @@ class MLP(nn.Module):
         return self.net(x)
 
 class BallPolicy(nn.Module):
-    def get_state_dims(n_balls: int):
+    def get_state_dims(n_balls: int, n_players: int = 1):
         """
         Calculates the # of features
-        n_balls: the number of balls in the game (excluding player ball)
+        n_balls: the number of balls in the game (excluding player balls)
+        n_players: the number of player balls per scene, the player's own & its teammates'
         """
-        return n_balls * 6 + 4
+        return n_balls * 6 + n_players * 4
 
-    def __init__(self, n_balls: int, n_actions: int, n_layers: int = 2, mlp_ratio: float = 4):
+    def __init__(self, n_balls: int, n_actions: int, n_layers: int = 2, mlp_ratio: float = 4, frame_stack: int = 1, n_players: int = 1):
         """
-        n_balls: the number of balls in the game (excluding player ball)
+        n_balls: the number of balls in the game (excluding player balls)
         nb_features: the number of features for each ball
         n_actions: the number of possible actions
         mlp_ratio: the ratio of hidden layer size to state space dimensions
+        frame_stack: the number of consecutive observations concatenated together
+        n_players: the number of player balls per scene
         """
         super().__init__()
-        state_dims = BallPolicy.get_state_dims(n_balls)
+        state_dims = BallPolicy.get_state_dims(n_balls, n_players) * frame_stack
         hidden_size = int(state_dims*mlp_ratio)
         self.pi = nn.Sequential(
             # MLP([state_dims, hidden_size, hidden_size, n_actions]),
@@ -61,6 +65,68 @@ class BallPolicy(nn.Module):
     def forward(self, s: torch.Tensor):
         return self.pi(s)
 
+class BallCNNPolicy(nn.Module):
+    def __init__(self, n_actions: int, n_channels: int = 6, resolution: int = 64, width: int = 32, frame_stack: int = 1):
+        """
+        Policy over top-down image observations of shape [B, n_channels, resolution, resolution]
+        n_actions: the number of possible actions
+        n_channels: one channel per ball class, the player & the walls
+        width: number of channels in the first conv layer
+        frame_stack: the number of consecutive images stacked along the channel dimension
+        """
+        super().__init__()
+        self.features = nn.Sequential(
+            nn.Conv2d(n_channels * frame_stack, width, kernel_size=5, stride=2, padding=2),
+            nn.ReLU(),
+            nn.Conv2d(width, width * 2, kernel_size=3, stride=2, padding=1),
+            nn.ReLU(),
+            nn.Conv2d(width * 2, width * 2, kernel_size=3, stride=2, padding=1),
+            nn.ReLU(),
+            nn.Flatten(),
+        )
+        feature_dims = width * 2 * (resolution // 8) ** 2
+        self.pi = MLP([feature_dims, 256, n_actions])
+
+    def forward(self, s: torch.Tensor):
+        return self.pi(self.features(s))
+
+class RecurrentPolicy(nn.Module):
+    def __init__(self, encoder: nn.Module, feature_dims: int, n_actions: int, hidden_size: int = 256, cell: str = "gru"):
+        """
+        Wraps a feed-forward policy body with a recurrent cell
+        encoder: maps an observation to `feature_dims` features
+        hidden_size: size of the recurrent state
+        cell: "gru" or "lstm". an lstm's (h, c) are packed into one [B, 2 * hidden_size] tensor
+        """
+        super().__init__()
+        self.encoder = encoder
+        self.hidden_size = hidden_size
+        self.is_lstm = cell == "lstm"
+        if self.is_lstm:
+            self.lstm = nn.LSTMCell(feature_dims, hidden_size)
+            self.gru = None
+        else:
+            self.gru = nn.GRUCell(feature_dims, hidden_size)
+            self.lstm = None
+        self.head = nn.Linear(hidden_size, n_actions)
+
+    @torch.jit.export
+    def initial_hidden(self, batch_size: int) -> torch.Tensor:
+        n = 2 if self.is_lstm else 1
+        return torch.zeros(batch_size, self.hidden_size * n)
+
+    def forward(self, s: torch.Tensor, h: torch.Tensor) -> Tuple[torch.Tensor, torch.Tensor]:
+        x = self.encoder(s)
+        if self.lstm is not None:
+            h_t, c_t = self.lstm(x, (h[:, :self.hidden_size], h[:, self.hidden_size:]))
+            h = torch.cat([h_t, c_t], dim=-1)
+            out = h_t
+        else:
+            assert self.gru is not None
+            h = self.gru(x, h)
+            out = h
+        return self.head(out), h
+
 # class PiApproximationWithNN():
 #     def __init__(self,
 #                  state_dims,
@@ -98,6 +164,28 @@ class BallPolicy(nn.Module):
 
 # build model into jit when this file is run
 if __name__ == "__main__":
-    model = BallPolicy(n_balls=50, n_actions=4, n_layers=5)
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--frame-stack", type=int, default=1, help="must match `--frame-stack` given to the game")
+    parser.add_argument("--cell", choices=["gru", "lstm"], default="gru", help="cell used by the `_rnn` policies")
+    parser.add_argument("--players", type=int, default=1, help="must match `--players` given to the game")
+    args = parser.parse_args()
+    k = args.frame_stack
+
+    model = BallPolicy(n_balls=50, n_actions=4, n_layers=5, frame_stack=k, n_players=args.players)
     model = torch.jit.script(model)
-    torch.jit.save(model, "ball_policy.pt")
\ No newline at end of file
+    torch.jit.save(model, "ball_policy.pt")
+
+    # policy for `--image-obs`
+    model = BallCNNPolicy(n_actions=4, frame_stack=k)
+    model = torch.jit.script(model)
+    torch.jit.save(model, "ball_policy_cnn.pt")
+
+    # policies for `--recurrent`, the body's last layer becomes the recurrent cell's input
+    state_dims = BallPolicy.get_state_dims(50, args.players) * k
+    encoder = MLP([state_dims, 256, 256])
+    model = RecurrentPolicy(encoder, 256, n_actions=4, cell=args.cell)
+    torch.jit.save(torch.jit.script(model), "ball_policy_rnn.pt")
+
+    cnn = BallCNNPolicy(n_actions=256, frame_stack=k)
+    model = RecurrentPolicy(cnn, 256, n_actions=4, cell=args.cell)
+    torch.jit.save(torch.jit.script(model), "ball_policy_cnn_rnn.pt")
\ No newline at end of file
@@ -0,0 +1,96 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use tch::Tensor;
+
+use crate::features::ball::*;
+use crate::scenes::ball_game_scene::{ARENA_HALF_SIZE, MAX_BALLS};
+
+use super::rasterizer::*;
+
+/// features per game ball: velocity, position & target quadrant
+pub const BALL_FEATURES: usize = 6;
+/// features of a player ball: velocity & position
+pub const PLAYER_FEATURES: usize = 4;
+
+/// What the policy is given to look at each step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObservationType {
+    /// flat list of ball positions and velocities
+    Vector,
+    /// top-down occupancy image with a channel per ball class
+    Image,
+}
+
+fn push(velocity: &Velocity, transform: &Transform, target_quadrant: Option<(i8, i8)>) -> Vec<f32> {
+    let mut inputs = Vec::new();
+    inputs.push(velocity.linvel.x);
+    inputs.push(velocity.linvel.z);
+    inputs.push(transform.translation.x);
+    inputs.push(transform.translation.z);
+    if let Some((v1, v2)) = target_quadrant {
+        inputs.append(&mut vec![v1 as f32, v2 as f32]);
+    }
+    inputs
+}
+
+/// Flat state vector of shape `[1, n_players * 4 + MAX_BALLS * 6]`, the
+/// player's own features first, then the other players' in the given order. Scenes
+/// with fewer balls in play are padded with zeros, which never describe a
+/// real ball since every real ball has a nonzero target quadrant
+pub fn vector_observation<'a>(
+    player: (&Velocity, &Transform),
+    teammates: impl Iterator<Item = (&'a Velocity, &'a Transform)>,
+    balls: impl Iterator<Item = (&'a Velocity, &'a Transform, &'a Ball)>,
+) -> Tensor {
+    let mut inputs = push(player.0, player.1, None);
+    for (velocity, transform) in teammates {
+        inputs.append(&mut push(velocity, transform, None));
+    }
+    let player_len = inputs.len();
+    for (velocity, transform, ball) in balls.take(MAX_BALLS) {
+        inputs.append(&mut push(velocity, transform, ball.class.target_quadrant()));
+    }
+    let len = (player_len + MAX_BALLS * BALL_FEATURES) as i64;
+    inputs.resize(len as usize, 0.);
+    Tensor::from_slice(&inputs).view([1, len])
+}
+
+/// Reflects a vector observation of `n_players` player balls across the
+/// x = 0 line, negating every x position, velocity & target quadrant
+pub fn mirror_x(observation: &Tensor, n_players: usize) -> Tensor {
+    let len = n_players * PLAYER_FEATURES + MAX_BALLS * BALL_FEATURES;
+    let mut signs = vec![1f32; len];
+    for i in 0..n_players {
+        signs[i * PLAYER_FEATURES] = -1.;
+        signs[i * PLAYER_FEATURES + 2] = -1.;
+    }
+    for i in 0..MAX_BALLS {
+        let offset = n_players * PLAYER_FEATURES + i * BALL_FEATURES;
+        signs[offset] = -1.;
+        signs[offset + 2] = -1.;
+        signs[offset + 4] = -1.;
+    }
+    observation * Tensor::from_slice(&signs).view([1, len as i64])
+}
+
+/// Top-down image of shape `[1, C, H, W]`, built from scene-local positions
+pub fn image_observation<'a>(
+    player: (&Transform, &Ball),
+    balls: impl Iterator<Item = (&'a Transform, &'a Ball)>,
+) -> Tensor {
+    let rasterizer = TopDownRasterizer::new(IMAGE_RESOLUTION, ARENA_HALF_SIZE);
+    let to_disc = |t: &Transform, b: &Ball| {
+        (
+            Vec2::new(t.translation.x, t.translation.z),
+            b.radius,
+            b.class,
+        )
+    };
+    let mut discs = vec![to_disc(player.0, player.1)];
+    for (transform, ball) in balls {
+        discs.push(to_disc(transform, ball));
+    }
+
+    let res = rasterizer.resolution as i64;
+    Tensor::from_slice(&rasterizer.rasterize(&discs)).view([1, IMAGE_CHANNELS as i64, res, res])
+}
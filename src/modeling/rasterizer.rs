@@ -0,0 +1,106 @@
+use bevy::prelude::*;
+
+use crate::features::ball::BallTag;
+
+/// image channels: one per ball class, then the player, then the walls
+pub const IMAGE_CHANNELS: usize = 6;
+pub const IMAGE_RESOLUTION: usize = 64;
+
+const PLAYER_CHANNEL: usize = 4;
+const WALL_CHANNEL: usize = 5;
+
+/// Draws a top-down occupancy image of a scene on the CPU
+///
+/// The image covers the arena plus a small border so that the walls
+/// are visible. Output is laid out channels-first `[C, H, W]`, with
+/// +x to the right and +z downwards.
+pub struct TopDownRasterizer {
+    pub resolution: usize,
+    pub arena_half_size: f32,
+    pub border: f32,
+}
+
+impl TopDownRasterizer {
+    pub fn new(resolution: usize, arena_half_size: f32) -> Self {
+        TopDownRasterizer {
+            resolution,
+            arena_half_size,
+            border: 1.0,
+        }
+    }
+
+    pub fn image_len(&self) -> usize {
+        IMAGE_CHANNELS * self.resolution * self.resolution
+    }
+
+    fn view_half_size(&self) -> f32 {
+        self.arena_half_size + self.border
+    }
+
+    fn pixel_size(&self) -> f32 {
+        self.view_half_size() * 2.0 / self.resolution as f32
+    }
+
+    /// world position of a pixel's center
+    fn pixel_center(&self, px: usize, pz: usize) -> Vec2 {
+        let size = self.pixel_size();
+        Vec2::new(
+            -self.view_half_size() + (px as f32 + 0.5) * size,
+            -self.view_half_size() + (pz as f32 + 0.5) * size,
+        )
+    }
+
+    fn channel(tag: BallTag) -> usize {
+        match tag {
+            BallTag::Red => 0,
+            BallTag::Blue => 1,
+            BallTag::Green => 2,
+            BallTag::Yellow => 3,
+            BallTag::Player => PLAYER_CHANNEL,
+        }
+    }
+
+    /// Rasterizes scene-local (x, z) ball positions into a flat image
+    pub fn rasterize(&self, balls: &[(Vec2, f32, BallTag)]) -> Vec<f32> {
+        let res = self.resolution;
+        let mut image = vec![0.0; self.image_len()];
+
+        // walls are anything outside of the playable area
+        for pz in 0..res {
+            for px in 0..res {
+                let p = self.pixel_center(px, pz);
+                if p.x.abs() >= self.arena_half_size || p.y.abs() >= self.arena_half_size {
+                    image[(WALL_CHANNEL * res + pz) * res + px] = 1.0;
+                }
+            }
+        }
+
+        for (position, radius, tag) in balls {
+            self.draw_disc(&mut image, Self::channel(*tag), *position, *radius);
+        }
+        image
+    }
+
+    /// Fills every pixel whose center is inside the disc; small discs
+    /// always mark at least the pixel they sit in
+    fn draw_disc(&self, image: &mut [f32], channel: usize, center: Vec2, radius: f32) {
+        let res = self.resolution as i64;
+        let size = self.pixel_size();
+        let to_pixel = |v: f32| ((v + self.view_half_size()) / size).floor() as i64;
+
+        let (cx, cz) = (to_pixel(center.x), to_pixel(center.y));
+        let reach = (radius / size).ceil() as i64;
+        for pz in (cz - reach).max(0)..=(cz + reach).min(res - 1) {
+            for px in (cx - reach).max(0)..=(cx + reach).min(res - 1) {
+                let inside = (px == cx && pz == cz)
+                    || self
+                        .pixel_center(px as usize, pz as usize)
+                        .distance_squared(center)
+                        <= radius * radius;
+                if inside {
+                    image[((channel as i64 * res + pz) * res + px) as usize] = 1.0;
+                }
+            }
+        }
+    }
+}
@@ -1,26 +1,185 @@
-use super::{ModelResource, Trajectory};
-use tch::Tensor;
+use super::{ModelResource, Trajectory, LEARNING_RATE};
+use tch::{Kind, Reduction, Tensor};
+
+const GAMMA: f32 = 0.99;
+/// number of steps gradients flow back through a recurrent policy
+pub const BPTT_LEN: usize = 64;
+
+/// Discounted return of each step, restarting at episode boundaries
+fn discounted_returns(rewards: &[f32], done: &[bool]) -> Vec<f32> {
+    let mut returns = vec![0.; rewards.len()];
+    let mut g = 0.;
+    for t in (0..rewards.len()).rev() {
+        if done[t] {
+            g = 0.;
+        }
+        g = rewards[t] + GAMMA * g;
+        returns[t] = g;
+    }
+    returns
+}
+
+/// Returns normalized to zero mean & unit std as a simple baseline. A
+/// single step has no spread to normalize by, so its return is kept as is
+fn normalized_returns(rewards: &[f32], done: &[bool]) -> Tensor {
+    let returns = Tensor::from_slice(&discounted_returns(rewards, done));
+    if rewards.len() < 2 {
+        return returns;
+    }
+    (&returns - returns.mean(Kind::Float)) / (returns.std(true) + 1e-8)
+}
+
+/// REINFORCE loss over a run of steps; actions are independent bernoullis
+fn policy_loss(logits: &Tensor, actions: &Tensor, returns: &Tensor) -> Tensor {
+    let log_prob = -logits
+        .binary_cross_entropy_with_logits::<Tensor>(actions, None, None, Reduction::None)
+        .sum_dim_intlist(-1, false, Kind::Float);
+    -(log_prob * returns).mean(Kind::Float)
+}
+
+/// Mean entropy of the action distribution, summed over the 4 bernoullis
+fn entropy(logits: &Tensor) -> f32 {
+    let probs = logits.sigmoid();
+    logits
+        .binary_cross_entropy_with_logits::<Tensor>(&probs, None, None, Reduction::None)
+        .sum_dim_intlist(-1, false, Kind::Float)
+        .mean(Kind::Float)
+        .double_value(&[]) as f32
+}
+
+/// How a call to `learn` went, averaged over its optimizer steps
+#[derive(Clone, Debug, Default)]
+pub struct TrainStats {
+    pub policy_loss: f32,
+    pub entropy: f32,
+    /// REINFORCE has no critic, so this is only set by value-based learners
+    pub value_loss: Option<f32>,
+    pub grad_norm: f32,
+    pub learning_rate: f64,
+    /// number of (state, action, reward) steps trained on
+    pub steps: usize,
+    updates: usize,
+}
+
+impl TrainStats {
+    fn record(&mut self, res: &ModelResource, loss: &Tensor, logits: &Tensor) {
+        self.policy_loss += loss.double_value(&[]) as f32;
+        self.entropy += tch::no_grad(|| entropy(logits));
+        self.grad_norm += grad_norm(res);
+        self.updates += 1;
+    }
+
+    fn finish(mut self) -> Self {
+        let n = self.updates.max(1) as f32;
+        self.policy_loss /= n;
+        self.entropy /= n;
+        self.grad_norm /= n;
+        self
+    }
+}
+
+/// L2 norm of every parameter's gradient after the last backward pass
+fn grad_norm(res: &ModelResource) -> f32 {
+    let sum_sq = res
+        ._vs
+        .trainable_variables()
+        .iter()
+        .map(|v| v.grad())
+        .filter(|g| g.defined())
+        .map(|g| g.square().sum(Kind::Float).double_value(&[]))
+        .sum::<f64>();
+    sum_sq.sqrt() as f32
+}
 
 /// trains model on batch of trajectories using REINFORCE algorithm
-/// ** in prog **
-pub fn learn(res: &mut ModelResource, trajectories: Vec<&Trajectory>) {
+pub fn learn(res: &mut ModelResource, trajectories: Vec<&Trajectory>) -> TrainStats {
+    let mut stats = TrainStats {
+        learning_rate: LEARNING_RATE,
+        ..Default::default()
+    };
+    res.model.set_train();
     for trajectory in trajectories.iter() {
-        // learn from the trajectory
-        // learn(&mut model, trajectory);
-        // states =
-        let states = Tensor::stack(&trajectory.state, 0);
-        let actions = Tensor::from_slice(&trajectory.action).unsqueeze(1);
-        let rewrds = Tensor::from_slice(&trajectory.reward).unsqueeze(1);
-        // let states = trajectory.state.iter().map(|s| s).collect::<Vec<_>>();
-        println!("Learning! num steps: {}", trajectory.state.len());
-        // for ((s, a), r) in trajectory
-        //     .state
-        //     .iter()
-        //     .zip(trajectory.action.iter())
-        //     .zip(trajectory.reward.iter())
-        // {
-        //     // Process each (state, action, reward) tuple
-        //     // Example: learn_from_tuple(state, action, reward);
-        // }
+        let n = trajectory.len();
+        if n == 0 {
+            continue;
+        }
+        println!("Learning! num steps: {}", n);
+        stats.steps += n;
+
+        let returns = normalized_returns(&trajectory.reward[..n], &trajectory.done[..n]);
+        let actions = Tensor::from_slice(&trajectory.action[..n].concat()).view([n as i64, 4]);
+
+        if !res.config.recurrent {
+            let states = Tensor::cat(&trajectory.state[..n], 0);
+            let (logits, _) = res.forward(&states, None, true);
+            let loss = policy_loss(&logits, &actions, &returns);
+            res.optimizer.backward_step(&loss);
+            stats.record(res, &loss, &logits);
+            continue;
+        }
+
+        // truncated BPTT: unroll step by step, resetting the hidden state
+        // after each episode end & detaching it between chunks
+        let mut h = match &trajectory.initial_hidden {
+            Some(h) => h.shallow_clone(),
+            None => res.initial_hidden(1),
+        };
+        for start in (0..n).step_by(BPTT_LEN) {
+            let end = (start + BPTT_LEN).min(n);
+            let mut logits = Vec::new();
+            for t in start..end {
+                let (l, h_next) = res.forward(&trajectory.state[t], Some(&h), true);
+                logits.push(l);
+                h = if trajectory.done[t] {
+                    res.initial_hidden(1)
+                } else {
+                    h_next.unwrap()
+                };
+            }
+            let logits = Tensor::cat(&logits, 0);
+            let loss = policy_loss(
+                &logits,
+                &actions.narrow(0, start as i64, (end - start) as i64),
+                &returns.narrow(0, start as i64, (end - start) as i64),
+            );
+            res.optimizer.backward_step(&loss);
+            stats.record(res, &loss, &logits);
+            h = h.detach();
+        }
+    }
+    res.model.set_eval();
+    stats.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::nn::{self, Module, OptimizerConfig};
+    use tch::Device;
+
+    #[test]
+    fn one_step_returns_are_finite() {
+        let returns = normalized_returns(&[1.5], &[true]);
+        assert_eq!(Vec::<f32>::try_from(returns).unwrap(), vec![1.5]);
+    }
+
+    #[test]
+    fn training_on_one_step_keeps_weights_finite() {
+        let vs = nn::VarStore::new(Device::Cpu);
+        let policy = nn::linear(vs.root(), 3, 4, Default::default());
+        let mut optimizer = nn::Adam::default().build(&vs, LEARNING_RATE).unwrap();
+
+        let states = Tensor::from_slice(&[0.5f32, -1.0, 2.0]).view([1, 3]);
+        let actions = Tensor::from_slice(&[1f32, 0., 0., 1.]).view([1, 4]);
+        let returns = normalized_returns(&[1.0], &[true]);
+        let logits = policy.forward(&states);
+        let loss = policy_loss(&logits, &actions, &returns);
+        assert!(loss.double_value(&[]).is_finite());
+        optimizer.backward_step(&loss);
+
+        for variable in vs.trainable_variables() {
+            let finite = variable.isfinite().all().int64_value(&[]);
+            assert_eq!(finite, 1);
+        }
     }
 }
@@ -1,55 +1,254 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use crate::features::ball::*;
 use crate::features::player_controllers::ControllerType;
+use crate::features::versus::Matchup;
+use crate::modeling::memory::PolicyMemory;
 use crate::modeling::Trajectory;
+use crate::util::curriculum::Curriculum;
+use crate::util::recording::EpisodeWriter;
+use crate::util::resources::{ProgramInputs, WorldSeed};
+use crate::util::reward::RewardState;
 
 use super::general;
+use super::isolation::scene_collisions;
+
+/// distance from the center of a scene to the inside of its walls
+pub const ARENA_HALF_SIZE: f32 = 25.0;
+/// number of game balls spawned in every scene, not all need be in play
+pub const MAX_BALLS: usize = 50;
+/// gap between the starting positions of a team's player balls
+const PLAYER_SPACING: f32 = 4.0;
+/// distance of each of two opposing teams from the x = 0 line at the start
+const TEAM_GAP: f32 = 6.0;
+
+const BALL_CLASSES: [BallTag; 4] = [BallTag::Red, BallTag::Blue, BallTag::Green, BallTag::Yellow];
+
+/// How a scene is laid out each time it is reset
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneParams {
+    /// game balls in play, at most `MAX_BALLS`
+    pub num_balls: usize,
+    /// how many of the 4 ball classes are used
+    pub num_colors: usize,
+    /// balls are scattered within this distance of the scene center
+    pub spawn_half_size: f32,
+}
+
+impl Default for SceneParams {
+    fn default() -> Self {
+        SceneParams {
+            num_balls: MAX_BALLS,
+            num_colors: BALL_CLASSES.len(),
+            spawn_half_size: 11.5,
+        }
+    }
+}
+
+/// Seed for a scene's layout in a given episode, mixed with splitmix64
+/// so neighbouring scenes & episodes get unrelated layouts
+pub fn episode_seed(world_seed: u64, scene: usize, episode: u64) -> u64 {
+    let mut z = world_seed ^ ((scene as u64) << 40) ^ episode;
+    z = z.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+impl SceneParams {
+    fn random_class(&self, rng: &mut impl Rng) -> BallTag {
+        BALL_CLASSES[rng.gen_range(0..self.num_colors.clamp(1, BALL_CLASSES.len()))]
+    }
+
+    fn random_position(&self, rng: &mut impl Rng) -> Vec3 {
+        let max = self.spawn_half_size;
+        Vec3::new(rng.gen_range(-max..max), 0.0, rng.gen_range(-max..max))
+    }
+}
+
+/// How a scene's player balls are split into teams, players are listed
+/// team by team
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Teams {
+    /// players per team
+    pub size: usize,
+    pub count: usize,
+}
+
+impl Default for Teams {
+    fn default() -> Self {
+        Teams { size: 1, count: 1 }
+    }
+}
+
+impl Teams {
+    pub fn players(&self) -> usize {
+        self.size.max(1) * self.count.max(1)
+    }
+
+    pub fn team_of(&self, k: usize) -> usize {
+        k / self.size.max(1)
+    }
+
+    /// Start of the `k`th player ball. A team lines up with its first player
+    /// in the middle & the rest alternating either side, along x for a lone
+    /// team, while two teams face each other across x = 0 as mirror images
+    fn start(&self, k: usize) -> Vec3 {
+        let j = k % self.size.max(1);
+        let side = |left: bool| if left { -1.0 } else { 1.0 };
+        let offset = ((j + 1) / 2) as f32 * PLAYER_SPACING * side(j % 2 == 0);
+        if self.count <= 1 {
+            return Vec3::new(offset, 0.0, 0.0);
+        }
+        Vec3::new(side(self.team_of(k) == 0) * TEAM_GAP, 0.0, offset)
+    }
+}
+
+/// One controllable ball of a scene & what its policy keeps about it
+pub struct Player {
+    pub ball: Entity,
+    pub team: usize,
+    pub trajectory: Trajectory,
+    pub memory: PolicyMemory,
+    /// steps of a human or the expert stored as demonstrations, see `HumanSteps`
+    pub demonstration: Trajectory,
+    /// most recent (up, down, left, right) input to the ball
+    pub last_action: (bool, bool, bool, bool),
+    /// (up, down, left, right) probabilities the policy gave on its last step
+    pub last_probs: Option<[f32; 4]>,
+}
+
+impl Player {
+    fn new(ball: Entity, team: usize) -> Self {
+        Player {
+            ball,
+            team,
+            trajectory: Trajectory::new(),
+            memory: PolicyMemory::new(),
+            demonstration: Trajectory::new(),
+            last_action: (false, false, false, false),
+            last_probs: None,
+        }
+    }
+}
 
 /// manages data collection of each game
 #[derive(Component)]
 pub struct BallGameScene {
-    pub trajectory: Trajectory,
+    /// index of the scene in the grid
+    pub id: usize,
+    /// number of episodes this scene has finished
+    pub episode: u64,
+    /// seed the current episode's layout was generated from
+    pub seed: u64,
+    /// seconds into the episode at which every ball was sorted
+    pub completed: Option<f32>,
+    /// the learner's team's reward
+    pub reward_state: RewardState,
+    /// the opposing team's reward, see `Versus`
+    pub opponent_reward: RewardState,
+    pub params: SceneParams,
+    /// balls in play
     pub game_balls: Vec<Entity>,
-    pub player_ball: Entity,
+    /// balls spawned but left out of play by `params`
+    pub benched_balls: Vec<Entity>,
+    /// the player balls, team by team, each team sharing a reward
+    pub players: Vec<Player>,
+    pub teams: Teams,
+    /// who plays each team in versus games
+    pub matchup: Matchup,
+    /// controller of the first player, teammates are never played by a human
     pub controller: ControllerType,
+    /// controller to hand back to once a human stops playing the scene
+    pub paused_controller: Option<ControllerType>,
+    /// draw the policy overlay for this scene
+    pub show_policy: bool,
+    /// streams the current episode to disk when recording
+    pub recorder: Option<EpisodeWriter>,
 }
 
-/// Creates a set of ball game scenes
+impl BallGameScene {
+    /// The first player, the one a human takes over & recordings follow
+    pub fn lead(&self) -> &Player {
+        &self.players[0]
+    }
+
+    /// Controller of the `k`th player. While a human plays the lead,
+    /// teammates keep to the controller the scene had before
+    pub fn controller_of(&self, k: usize) -> ControllerType {
+        match (k, self.controller) {
+            (0, controller) => controller,
+            (_, ControllerType::Keyboard) => self
+                .paused_controller
+                .unwrap_or(ControllerType::AI { training: true }),
+            (_, controller) => controller,
+        }
+    }
+}
+
+/// Spawns the grid of scenes, drawn only when the renderer's asset stores
+/// are there (not in headless runs)
 pub fn setup_world(
     mut commands: Commands,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: Option<ResMut<Assets<StandardMaterial>>>,
+    mut meshes: Option<ResMut<Assets<Mesh>>>,
+    curriculum: Option<Res<Curriculum>>,
+    world_seed: Res<WorldSeed>,
+    program_inputs: Res<ProgramInputs>,
 ) {
+    let teams = Teams {
+        size: program_inputs.players,
+        count: if program_inputs.versus { 2 } else { 1 },
+    };
+    let mut assets = match (materials.as_deref_mut(), meshes.as_deref_mut()) {
+        (Some(materials), Some(meshes)) => Some(SceneAssets { meshes, materials }),
+        _ => None,
+    };
     // create stages
     let grid_size = 6;
     for i in 0..grid_size {
         for j in 0..grid_size {
             let x = (i as f32 - grid_size as f32 / 2.0) * 60.0;
             let z = (j as f32 - grid_size as f32 / 2.0) * 60.0;
+            let id = i * grid_size + j;
+            let params = match &curriculum {
+                Some(curriculum) => curriculum.params_for(id),
+                None => SceneParams::default(),
+            };
             setup_scene(
                 &mut commands,
+                id,
+                params,
+                episode_seed(world_seed.0, id, 0),
                 Vec3::new(x, 0.0, z),
-                &mut materials,
-                &mut meshes,
+                teams,
+                assets.as_mut(),
             );
         }
     }
 
     // add light
-    commands.insert_resource(AmbientLight {
-        color: Color::srgb(0.3, 0.3, 0.3),
-        brightness: 25_000.0,
-    });
+    if assets.is_some() {
+        commands.insert_resource(AmbientLight {
+            color: Color::srgb(0.3, 0.3, 0.3),
+            brightness: 25_000.0,
+        });
+    }
 }
 
-fn setup_scene(
+/// Spawns a scene's physics, plus its meshes & materials when `assets` are given
+pub fn setup_scene(
     commands: &mut Commands,
+    id: usize,
+    params: SceneParams,
+    seed: u64,
     center: Vec3, // Add this parameter
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    meshes: &mut ResMut<Assets<Mesh>>,
+    teams: Teams,
+    assets: Option<&mut SceneAssets>,
 ) {
     let parent_entity = commands
         .spawn((
@@ -60,22 +259,23 @@ fn setup_scene(
 
     let mut scene = None;
     commands.entity(parent_entity).with_children(|parent| {
-        scene = Some(_setup_scene(parent, materials, meshes));
+        scene = Some(_setup_scene(parent, id, params, seed, teams, assets));
     });
     commands.entity(parent_entity).insert(scene.unwrap());
 }
 
 fn _setup_scene(
     parent: &mut ChildBuilder,
-    mut materials: &mut ResMut<Assets<StandardMaterial>>,
-    mut meshes: &mut ResMut<Assets<Mesh>>,
+    id: usize,
+    params: SceneParams,
+    seed: u64,
+    teams: Teams,
+    mut assets: Option<&mut SceneAssets>,
 ) -> BallGameScene {
     let tile_size = 1.0;
     let width = 25; // Number of tiles along the width
     let length = 25; // Number of tiles along the length
 
-    let mesh_handle = general::create_checkerboard_mesh(meshes, width, length, tile_size);
-
     let colors = [
         Color::srgba_u8(38, 70, 83, 100),    // Blue
         Color::srgba_u8(233, 196, 106, 100), // Yellow
@@ -87,28 +287,35 @@ fn _setup_scene(
         .with_children(|parent| {
             let total_width: f32 = width as f32 * tile_size;
             let total_length = length as f32 * tile_size;
-            for (i, &base_color) in colors.iter().enumerate() {
-                let x_offset = (i % 2) as f32 * total_width - (total_width / 2.0); // Adjust x position
-                let z_offset = (i / 2) as f32 * total_length - (total_length / 2.0); // Adjust z position
-
-                parent.spawn(PbrBundle {
-                    mesh: mesh_handle.clone(), // Reuse the handle, not the mesh data
-                    material: materials.add(StandardMaterial {
-                        base_color,
-                        alpha_mode: AlphaMode::Blend,
-                        cull_mode: None,
+            // the floor is only drawn, balls are kept on it by `LockedAxes`
+            if let Some(assets) = assets.as_deref_mut() {
+                let mesh_handle =
+                    general::create_checkerboard_mesh(assets.meshes, width, length, tile_size);
+                for (i, &base_color) in colors.iter().enumerate() {
+                    let x_offset = (i % 2) as f32 * total_width - (total_width / 2.0); // Adjust x position
+                    let z_offset = (i / 2) as f32 * total_length - (total_length / 2.0); // Adjust z position
+
+                    parent.spawn(PbrBundle {
+                        mesh: mesh_handle.clone(), // Reuse the handle, not the mesh data
+                        material: assets.materials.add(StandardMaterial {
+                            base_color,
+                            alpha_mode: AlphaMode::Blend,
+                            cull_mode: None,
+                            ..Default::default()
+                        }),
+                        transform: Transform::from_xyz(x_offset, 0.0, z_offset),
                         ..Default::default()
-                    }),
-                    transform: Transform::from_xyz(x_offset, 0.0, z_offset),
-                    ..Default::default()
-                });
+                    });
+                }
             }
 
             // Spawning walls
-            let wall_material = materials.add(StandardMaterial {
-                base_color: Color::srgb(1.0, 1.0, 1.0), // White walls
-                alpha_mode: AlphaMode::Opaque,
-                ..Default::default()
+            let wall_material = assets.as_deref_mut().map(|assets| {
+                assets.materials.add(StandardMaterial {
+                    base_color: Color::srgb(1.0, 1.0, 1.0), // White walls
+                    alpha_mode: AlphaMode::Opaque,
+                    ..Default::default()
+                })
             });
 
             let wall_height = 1.0; // Example height of the walls
@@ -143,99 +350,200 @@ fn _setup_scene(
             ];
 
             for (transform, width, height, depth) in walls.iter() {
-                parent
-                    .spawn(PbrBundle {
-                        mesh: meshes.add(Mesh::from(Cuboid {
+                let mut wall = parent.spawn((
+                    TransformBundle::from_transform(*transform),
+                    Collider::cuboid(*width, *height, *depth),
+                    Restitution::coefficient(1.0),
+                    scene_collisions(id),
+                ));
+                if let (Some(assets), Some(material)) = (assets.as_deref_mut(), &wall_material) {
+                    wall.insert((
+                        assets.meshes.add(Mesh::from(Cuboid {
                             half_size: Vec3::new(*width, *height, *depth),
                         })),
-                        material: wall_material.clone(),
-                        transform: *transform,
-                        ..Default::default()
-                    })
-                    .insert((
-                        Collider::cuboid(*width, *height, *depth),
-                        Restitution::coefficient(1.0),
+                        material.clone(),
+                        VisibilityBundle::default(),
                     ));
+                }
             }
         });
 
-    // spawn random balls
-    let mut rng = rand::thread_rng();
+    // spawn random balls, those past `params.num_balls` start benched
+    let mut rng = StdRng::seed_from_u64(seed);
     let mut game_balls = Vec::new();
-    let num_balls = 50;
+    let mut benched_balls = Vec::new();
     let ball_radius = 0.5;
-    for _ in 0..num_balls {
-        let x_max = width as f32 * tile_size / 2.0 - ball_radius * 2.0;
-        let z_max = length as f32 * tile_size / 2.0 - ball_radius * 2.0;
-        let x_pos = rng.gen_range(-x_max..x_max);
-        let z_pos = rng.gen_range(-z_max..z_max);
-
-        let position = Vec3::new(x_pos, 0.0, z_pos);
+    for i in 0..MAX_BALLS {
+        let position = params.random_position(&mut rng);
         let velocity = Vec3::new(0.0, 0.0, 0.0);
-        let class = match rng.gen_range(0..4) {
-            0 => BallTag::Red,
-            1 => BallTag::Blue,
-            2 => BallTag::Green,
-            _ => BallTag::Yellow,
-        };
-
-        game_balls.push(
-            Ball::spawn(
-                ball_radius,
-                position,
-                velocity,
-                class,
-                parent,
-                &mut meshes,
-                &mut materials,
-            )
-            .id(),
+        let class = params.random_class(&mut rng);
+
+        let mut ball = Ball::spawn(
+            ball_radius,
+            position,
+            velocity,
+            class,
+            parent,
+            assets.as_deref_mut(),
         );
+        ball.insert(scene_collisions(id));
+        if i < params.num_balls {
+            game_balls.push(ball.id());
+        } else {
+            ball.insert((RigidBodyDisabled, ColliderDisabled));
+            if assets.is_some() {
+                ball.insert(Visibility::Hidden);
+            }
+            benched_balls.push(ball.id());
+        }
     }
 
-    let player_ball = ControllableBall::spawn(
-        Vec3::new(0.0, 0.0, 0.0),
-        // commands,
-        parent,
-        &mut meshes,
-        &mut materials,
-    );
+    let players = (0..teams.players())
+        .map(|k| {
+            let team = teams.team_of(k);
+            let ball = ControllableBall::spawn(
+                teams.start(k),
+                team,
+                // commands,
+                parent,
+                assets.as_deref_mut(),
+            )
+            .insert(scene_collisions(id))
+            .id();
+            Player::new(ball, team)
+        })
+        .collect();
 
     BallGameScene {
-        trajectory: Trajectory::new(),
+        id,
+        episode: 0,
+        seed,
+        completed: None,
+        reward_state: RewardState::default(),
+        opponent_reward: RewardState::default(),
+        params,
         game_balls,
-        player_ball,
+        benched_balls,
+        players,
+        teams,
+        matchup: Matchup::default(),
         controller: ControllerType::AI { training: true },
         // controller: ControllerType::Keyboard,
+        paused_controller: None,
+        show_policy: false,
+        recorder: None,
     }
     // how do add scene as a component to parent entity??
 }
 
-/// Resets balls back to random starting position
-pub fn reset_scene(
-    mut param_set: ParamSet<(
-        Query<(&mut Velocity, &mut Transform), With<Ball>>,
-        Query<(&mut Velocity, &mut Transform), With<ControllableBall>>,
-    )>,
+fn recolor(
+    material: Option<&Handle<StandardMaterial>>,
+    materials: Option<&mut Assets<StandardMaterial>>,
+    class: BallTag,
 ) {
-    let mut rng = rand::thread_rng();
-    let width = 25.0; // Assuming the width of the area
-    let length = 25.0; // Assuming the length of the area
+    if let (Some(handle), Some(materials)) = (material, materials) {
+        if let Some(material) = materials.get_mut(handle) {
+            material.base_color = class.color();
+        }
+    }
+}
 
-    // reset other balls
-    for (mut velocity, mut transform) in param_set.p0().iter_mut() {
-        let x_max = width / 2.0 - 1.0;
-        let z_max = length / 2.0 - 1.0;
-        let x_pos = rng.gen_range(-x_max..x_max);
-        let z_pos = rng.gen_range(-z_max..z_max);
+/// Everything needed to rearrange the balls of a scene
+#[derive(SystemParam)]
+pub struct SceneBalls<'w, 's> {
+    commands: Commands<'w, 's>,
+    balls: Query<
+        'w,
+        's,
+        (
+            &'static mut Velocity,
+            &'static mut Transform,
+            &'static mut Ball,
+            Option<&'static mut Visibility>,
+            Option<&'static Handle<StandardMaterial>>,
+        ),
+    >,
+    materials: Option<ResMut<'w, Assets<StandardMaterial>>>,
+}
+
+impl SceneBalls<'_, '_> {
+    pub fn get(&self, entity: Entity) -> Option<(&Transform, &Ball)> {
+        let (_, transform, ball, _, _) = self.balls.get(entity).ok()?;
+        Some((transform, ball))
+    }
+
+    /// Moves a ball to a scene-local (x, z) position with the given velocity
+    pub fn place(&mut self, entity: Entity, position: Vec2, velocity: Vec2) {
+        if let Ok((mut v, mut transform, _, _, _)) = self.balls.get_mut(entity) {
+            transform.translation = Vec3::new(position.x, 0.0, position.y);
+            v.linvel = Vec3::new(velocity.x, 0.0, velocity.y);
+        }
+    }
 
-        transform.translation = Vec3::new(x_pos, 0.0, z_pos);
-        velocity.linvel = Vec3::new(0.0, 0.0, 0.0);
+    /// Changes a ball's class, recoloring it to match
+    pub fn set_class(&mut self, entity: Entity, class: BallTag) {
+        if let Ok((_, _, mut ball, _, material)) = self.balls.get_mut(entity) {
+            if ball.class != class {
+                ball.class = class;
+                recolor(material, self.materials.as_deref_mut(), class);
+            }
+        }
     }
 
-    // Reset controllable ball
-    for (mut velocity, mut transform) in param_set.p1().iter_mut() {
-        transform.translation = Vec3::new(0.0, 0.0, 0.0);
-        velocity.linvel = Vec3::new(0.0, 0.0, 0.0);
+    /// Resets balls back to random starting position following the
+    /// scene's `params` & `seed`, benching or un-benching balls as needed
+    pub fn reset(&mut self, scene: &mut BallGameScene) {
+        let mut rng = StdRng::seed_from_u64(scene.seed);
+        let params = scene.params;
+
+        let mut all_balls = std::mem::take(&mut scene.game_balls);
+        all_balls.append(&mut scene.benched_balls);
+        scene.benched_balls = all_balls.split_off(params.num_balls.min(all_balls.len()));
+        scene.game_balls = all_balls;
+
+        // reset balls in play
+        for entity in scene.game_balls.iter() {
+            let Ok((mut velocity, mut transform, mut ball, visibility, material)) =
+                self.balls.get_mut(*entity)
+            else {
+                continue;
+            };
+            transform.translation = params.random_position(&mut rng);
+            velocity.linvel = Vec3::ZERO;
+
+            let class = params.random_class(&mut rng);
+            if ball.class != class {
+                ball.class = class;
+                recolor(material, self.materials.as_deref_mut(), class);
+            }
+            if let Some(mut visibility) = visibility {
+                *visibility = Visibility::Inherited;
+            }
+            self.commands
+                .entity(*entity)
+                .remove::<(RigidBodyDisabled, ColliderDisabled)>();
+        }
+
+        // take the rest out of play
+        for entity in scene.benched_balls.iter() {
+            let Ok((mut velocity, _, _, visibility, _)) = self.balls.get_mut(*entity) else {
+                continue;
+            };
+            velocity.linvel = Vec3::ZERO;
+            if let Some(mut visibility) = visibility {
+                *visibility = Visibility::Hidden;
+            }
+            self.commands
+                .entity(*entity)
+                .insert((RigidBodyDisabled, ColliderDisabled));
+        }
+
+        // Reset controllable balls
+        for (k, player) in scene.players.iter().enumerate() {
+            if let Ok((mut velocity, mut transform, _, _, _)) = self.balls.get_mut(player.ball) {
+                transform.translation = scene.teams.start(k);
+                velocity.linvel = Vec3::new(0.0, 0.0, 0.0);
+            }
+        }
     }
 }
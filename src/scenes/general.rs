@@ -3,7 +3,7 @@ use bevy::render::mesh::PrimitiveTopology;
 use bevy::render::render_asset::RenderAssetUsages;
 
 pub fn create_checkerboard_mesh(
-    meshes: &mut ResMut<Assets<Mesh>>,
+    meshes: &mut Assets<Mesh>,
     width: usize,
     length: usize,
     tile_size: f32,
@@ -0,0 +1,45 @@
+//! Keeps every scene's colliders to themselves. All scenes share one
+//! Rapier world, so each scene's colliders get a collision group of their
+//! own, and since there are only 32 groups, scenes past the 32nd share one
+//! and are told apart by a contact filter hook
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+/// number of collision groups Rapier has
+const GROUPS: usize = 32;
+
+/// Scene a collider belongs to
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InScene(pub usize);
+
+/// Collision group, filter & scene tag for the colliders of scene `id`
+pub fn scene_collisions(id: usize) -> impl Bundle {
+    let group = Group::from_bits_truncate(1 << (id % GROUPS));
+    // a pair is filtered if either collider asks, so flagging the scenes
+    // that reuse a group covers every pair sharing one
+    let hooks = if id >= GROUPS {
+        ActiveHooks::FILTER_CONTACT_PAIR
+    } else {
+        ActiveHooks::empty()
+    };
+    (InScene(id), CollisionGroups::new(group, group), hooks)
+}
+
+/// Drops contacts between colliders of different scenes that share a group
+#[derive(SystemParam)]
+pub struct SceneIsolation<'w, 's> {
+    scenes: Query<'w, 's, &'static InScene>,
+}
+
+impl BevyPhysicsHooks for SceneIsolation<'_, '_> {
+    fn filter_contact_pair(&self, context: PairFilterContextView) -> Option<SolverFlags> {
+        let scene = |entity| self.scenes.get(entity).ok();
+        if scene(context.collider1()) == scene(context.collider2()) {
+            Some(SolverFlags::COMPUTE_IMPULSES)
+        } else {
+            None
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod ball_game_scene;
 pub mod general;
+pub mod isolation;
 
 pub use ball_game_scene as BallGameScene;
@@ -0,0 +1,53 @@
+//! Table-free CRC-32 for file formats that need a checksum
+
+/// Reflected CRC-32 with the given polynomial, init & final xor of `!0`
+fn crc32_with(poly: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (poly & mask);
+        }
+    }
+    !crc
+}
+
+/// CRC-32 (IEEE) as used by zip
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_with(0xEDB8_8320, bytes)
+}
+
+/// CRC-32C (Castagnoli) as used by TensorBoard records
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    crc32_with(0x82F6_3B78, bytes)
+}
+
+/// TFRecord's masked CRC-32C, so CRCs of data holding CRCs stay well mixed
+pub fn masked_crc32c(bytes: &[u8]) -> u32 {
+    let crc = crc32c(bytes);
+    (crc.rotate_right(15)).wrapping_add(0xA282_EAD8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_matches_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn crc32c_matches_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    }
+
+    #[test]
+    fn masked_crc32c_rotates_then_adds_the_delta() {
+        // 0xE3069283 rotated right by 15 is 0x2507C60D, plus 0xA282EAD8
+        assert_eq!(masked_crc32c(b"123456789"), 0xC78A_B0E5);
+        assert_eq!(masked_crc32c(b""), 0xA282_EAD8);
+    }
+}
@@ -0,0 +1,84 @@
+use bevy::prelude::Resource;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Run settings loaded from a file of `key = value` lines,
+/// `#` starts a comment
+#[derive(Resource, Default, Debug)]
+pub struct Config {
+    values: BTreeMap<String, String>,
+    /// defaults handed out for missing keys, see `resolved`
+    defaults: Mutex<BTreeMap<String, String>>,
+}
+
+impl Clone for Config {
+    fn clone(&self) -> Self {
+        Config {
+            values: self.values.clone(),
+            defaults: Mutex::new(self.defaults.lock().unwrap().clone()),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        Ok(Config::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut config = Config::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if let Some((key, value)) = line.split_once('=') {
+                config.set(key.trim(), value.trim());
+            }
+        }
+        config
+    }
+
+    /// Value for `key`, or `default` when it is missing or can't be parsed
+    pub fn get<T: FromStr + ToString>(&self, key: &str, default: T) -> T {
+        match self.values.get(key) {
+            None => {
+                let mut defaults = self.defaults.lock().unwrap();
+                defaults.insert(key.to_string(), default.to_string());
+                default
+            }
+            Some(value) => value.parse().unwrap_or_else(|_| {
+                eprintln!("Config: could not parse {} = {}, using default", key, value);
+                default
+            }),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.values.iter()
+    }
+
+    /// The values set plus every default that has been read so far,
+    /// i.e. the settings the run actually used
+    pub fn resolved(&self) -> Config {
+        let mut resolved = self.clone();
+        for (key, value) in self.defaults.lock().unwrap().iter() {
+            resolved.values.entry(key.clone()).or_insert(value.clone());
+        }
+        resolved
+    }
+}
+
+/// Writes the config back out in the same `key = value` format it is read in
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in self.values.iter() {
+            writeln!(f, "{} = {}", key, value)?;
+        }
+        Ok(())
+    }
+}
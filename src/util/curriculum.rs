@@ -0,0 +1,117 @@
+use bevy::prelude::*;
+use std::collections::{HashMap, VecDeque};
+
+use crate::scenes::ball_game_scene::{SceneParams, MAX_BALLS};
+use crate::util::config::Config;
+use crate::util::episode::EpisodeOutcome;
+
+/// Where a single scene is in the curriculum
+#[derive(Default)]
+struct SceneProgress {
+    level: usize,
+    recent: VecDeque<bool>,
+}
+
+/// One `balls:colors:spawn_half_size` level of `curriculum.levels`
+fn parse_level(level: &str) -> Result<SceneParams, String> {
+    let level = level.trim();
+    let bad = |why: &str| format!("bad curriculum level `{}`: {}", level, why);
+    let [balls, colors, half_size] = level.split(':').collect::<Vec<_>>()[..] else {
+        return Err(bad("expected balls:colors:spawn_half_size"));
+    };
+    let num_balls = balls
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| bad("balls is not a whole number"))?;
+    let num_colors = colors
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| bad("colors is not a whole number"))?;
+    let spawn_half_size = half_size
+        .trim()
+        .parse::<f32>()
+        .map_err(|_| bad("spawn_half_size is not a number"))?;
+    if num_colors < 1 {
+        return Err(bad("needs at least one color"));
+    }
+    // balls spawn in (-spawn_half_size, spawn_half_size)
+    if !(spawn_half_size > 0.0 && spawn_half_size.is_finite()) {
+        return Err(bad("spawn_half_size must be above 0"));
+    }
+    Ok(SceneParams {
+        num_balls: num_balls.min(MAX_BALLS),
+        num_colors,
+        spawn_half_size,
+    })
+}
+
+/// Starts every scene on an easy layout and moves it to the next level
+/// once its rolling success rate crosses `threshold`
+#[derive(Resource)]
+pub struct Curriculum {
+    pub levels: Vec<SceneParams>,
+    /// success rate needed to advance
+    pub threshold: f32,
+    /// number of recent episodes the success rate is taken over
+    pub window: usize,
+    progress: HashMap<usize, SceneProgress>,
+}
+
+impl Curriculum {
+    pub fn new(levels: Vec<SceneParams>, threshold: f32, window: usize) -> Self {
+        assert!(!levels.is_empty(), "curriculum needs at least one level");
+        Curriculum {
+            levels,
+            threshold,
+            window: window.max(1),
+            progress: HashMap::new(),
+        }
+    }
+
+    /// Reads `curriculum.*` entries, `None` unless `curriculum.enabled = true`.
+    /// Levels are written `balls:colors:spawn_half_size`, separated by commas
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        if !config.get("curriculum.enabled", false) {
+            return Ok(None);
+        }
+        let default_levels = "4:2:6, 10:2:8, 20:4:11.5, 35:4:11.5, 50:4:11.5";
+        let levels = config
+            .get("curriculum.levels", default_levels.to_string())
+            .split(',')
+            .map(parse_level)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(Curriculum::new(
+            levels,
+            config.get("curriculum.threshold", 0.8),
+            config.get("curriculum.window", 10),
+        )))
+    }
+
+    pub fn level_of(&self, scene: usize) -> usize {
+        self.progress.get(&scene).map_or(0, |p| p.level)
+    }
+
+    pub fn params_for(&self, scene: usize) -> SceneParams {
+        self.levels[self.level_of(scene)]
+    }
+
+    /// Adds an episode to its scene's history, returning the new level
+    /// if the scene just advanced
+    pub fn record(&mut self, outcome: &EpisodeOutcome) -> Option<usize> {
+        let (n_levels, window, threshold) = (self.levels.len(), self.window, self.threshold);
+        let progress = self.progress.entry(outcome.scene).or_default();
+        progress.recent.push_back(outcome.success);
+        while progress.recent.len() > window {
+            progress.recent.pop_front();
+        }
+
+        let successes = progress.recent.iter().filter(|s| **s).count();
+        let rate = successes as f32 / window as f32;
+        if rate >= threshold && progress.level + 1 < n_levels {
+            progress.level += 1;
+            progress.recent.clear();
+            return Some(progress.level);
+        }
+        None
+    }
+}
@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+
+use crate::features::ball::Ball;
+use crate::util::config::Config;
+
+/// When a scene counts as sorted
+#[derive(Resource, Clone, Debug)]
+pub struct EpisodeConfig {
+    /// how far past the quadrant boundary a ball must be to count as sorted,
+    /// so balls sitting on the line don't complete the game
+    pub completion_margin: f32,
+}
+
+impl EpisodeConfig {
+    pub fn from_config(config: &Config) -> Self {
+        EpisodeConfig {
+            completion_margin: config.get("episode.completion_margin", 0.0),
+        }
+    }
+}
+
+/// Result of a single scene's episode
+#[derive(Clone, Debug)]
+pub struct EpisodeOutcome {
+    pub scene: usize,
+    pub episode: u64,
+    /// every ball ended up in its quadrant
+    pub success: bool,
+    /// seconds from episode start until every ball was sorted
+    pub time_to_complete: Option<f32>,
+    /// fraction of balls sorted when the episode ended
+    pub final_sorted_fraction: f32,
+    /// sum of the rewards given over the episode
+    pub episode_return: f32,
+    /// the opposing team's return, in versus games
+    pub opponent_return: Option<f32>,
+    /// scene was played by a human
+    pub human: bool,
+    /// scene was played by the scripted expert
+    pub scripted: bool,
+}
+
+/// Fraction of `balls` in their quadrant (by at least `margin`)
+pub fn sorted_fraction<'a>(
+    balls: impl Iterator<Item = (&'a Transform, &'a Ball)>,
+    margin: f32,
+) -> f32 {
+    let (mut sorted, mut total) = (0, 0);
+    for (transform, ball) in balls {
+        total += 1;
+        if ball.correct_quadrant_with_margin(
+            transform.translation.x,
+            transform.translation.z,
+            margin,
+        ) {
+            sorted += 1;
+        }
+    }
+    sorted as f32 / total.max(1) as f32
+}
+
+/// Prints success rate, time-to-complete & sorted fraction over outcomes
+pub fn print_outcome_summary(label: &str, outcomes: &[EpisodeOutcome]) {
+    if outcomes.is_empty() {
+        return;
+    }
+    let n = outcomes.len() as f32;
+    let successes = outcomes.iter().filter(|o| o.success).count();
+    let times = outcomes
+        .iter()
+        .filter_map(|o| o.time_to_complete)
+        .collect::<Vec<f32>>();
+    let mean_time = times.iter().sum::<f32>() / times.len().max(1) as f32;
+    let mean_sorted = outcomes
+        .iter()
+        .map(|o| o.final_sorted_fraction)
+        .sum::<f32>()
+        / n;
+    println!(
+        "{}: success rate {:.2} ({}/{}), mean time-to-complete {:.2}s, mean sorted fraction {:.3}",
+        label,
+        successes as f32 / n,
+        successes,
+        outcomes.len(),
+        mean_time,
+        mean_sorted
+    );
+}
+
+/// Prints how often the learner's team out-scored the opponent, in versus games
+pub fn print_versus_summary(outcomes: &[EpisodeOutcome]) {
+    let games = outcomes
+        .iter()
+        .filter_map(|o| {
+            o.opponent_return
+                .map(|opponent| (o.episode_return, opponent))
+        })
+        .collect::<Vec<_>>();
+    if games.is_empty() {
+        return;
+    }
+    let wins = games.iter().filter(|(own, other)| own > other).count();
+    let n = games.len() as f32;
+    println!(
+        "Versus: won {}/{} games, mean return {:.3} against {:.3}",
+        wins,
+        games.len(),
+        games.iter().map(|(own, _)| own).sum::<f32>() / n,
+        games.iter().map(|(_, other)| other).sum::<f32>() / n
+    );
+}
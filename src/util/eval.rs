@@ -0,0 +1,506 @@
+//! Evaluation harness: plays one or more policies greedily over the same
+//! fixed suite of layouts and reports how well each sorts
+//!
+//! Every (seed, repeat) pair of the suite is one episode, laid out from
+//! `episode_seed(seed, 0, repeat)`, so policies evaluated with the same
+//! `eval.seeds` & `eval.episodes` see identical layouts. Results go to
+//! the run's `eval/episodes.csv` & `eval/report.json`
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::features::ball::{Ball, BallTag};
+use crate::features::player_controllers::ControllerType;
+use crate::modeling::ModelResource;
+use crate::scenes::ball_game_scene::{episode_seed, BallGameScene, SceneBalls, SceneParams};
+use crate::util::config::Config;
+use crate::util::episode::{sorted_fraction, EpisodeConfig, EpisodeOutcome};
+use crate::util::events::SimulationEndedEvent;
+use crate::util::run::{or_null, RunDir};
+
+/// classes broken out in the report, in `BallTag::to_u8` order
+const CLASSES: [BallTag; 4] = [BallTag::Red, BallTag::Blue, BallTag::Green, BallTag::Yellow];
+/// z for a two-sided 95% normal confidence interval
+const Z_95: f32 = 1.96;
+
+/// One layout of the suite
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EvalJob {
+    pub seed: u64,
+    pub repeat: u64,
+}
+
+/// A policy's episode on one layout
+#[derive(Clone, Debug)]
+pub struct EvalEpisode {
+    pub policy: usize,
+    pub job: EvalJob,
+    pub outcome: EpisodeOutcome,
+    /// balls of each class sorted & in play when the episode ended
+    pub class_counts: [(u32, u32); 4],
+}
+
+/// Mean, median & 95% confidence interval half-width of a sample
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Summary {
+    pub n: usize,
+    pub mean: f32,
+    pub median: f32,
+    pub ci95: f32,
+}
+
+impl Summary {
+    /// The interval uses the normal approximation, fine for the tens of
+    /// episodes a suite usually has
+    pub fn of(values: &[f32]) -> Self {
+        let n = values.len();
+        if n == 0 {
+            return Summary::default();
+        }
+        let mean = values.iter().sum::<f32>() / n as f32;
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = if n % 2 == 0 {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+        } else {
+            sorted[n / 2]
+        };
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>()
+            / n.saturating_sub(1).max(1) as f32;
+        Summary {
+            n,
+            mean,
+            median,
+            ci95: Z_95 * variance.sqrt() / (n as f32).sqrt(),
+        }
+    }
+
+    fn to_json(self) -> String {
+        format!(
+            "{{\"n\": {}, \"mean\": {}, \"median\": {}, \"ci95\": {}}}",
+            self.n,
+            json_value(self.mean),
+            json_value(self.median),
+            json_value(self.ci95)
+        )
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.3} ± {:.3} (median {:.3}, n={})",
+            self.mean, self.ci95, self.median, self.n
+        )
+    }
+}
+
+/// `eval.seeds`, a comma-separated list or an `a..b` range, with at
+/// least one seed so every summary has episodes to summarize
+fn parse_seeds(seeds: &str) -> Result<Vec<u64>, String> {
+    let parse = |seed: &str| {
+        seed.trim().parse::<u64>().map_err(|_| {
+            format!(
+                "bad eval.seeds `{}`: `{}` is not a seed",
+                seeds,
+                seed.trim()
+            )
+        })
+    };
+    let seeds_list = match seeds.split_once("..") {
+        Some((start, end)) => (parse(start)?..parse(end)?).collect::<Vec<_>>(),
+        None => seeds.split(',').map(parse).collect::<Result<Vec<_>, _>>()?,
+    };
+    if seeds_list.is_empty() {
+        return Err(format!("eval.seeds `{}` has no seeds", seeds));
+    }
+    Ok(seeds_list)
+}
+
+/// Runs the suite for each policy in turn, across every scene at once
+#[derive(Resource)]
+pub struct Evaluation {
+    /// checkpoints to evaluate, the first is the baseline others are compared to
+    pub policies: Vec<String>,
+    pub seeds: Vec<u64>,
+    /// episodes per seed
+    pub episodes: u64,
+    pub results: Vec<EvalEpisode>,
+    /// policy being evaluated
+    policy: usize,
+    /// next job of `jobs()` to hand out
+    next_job: usize,
+    /// what each scene is playing, by scene id. Scenes left over once the
+    /// suite is handed out sit idle
+    assigned: HashMap<usize, EvalJob>,
+    /// class counts of the scenes that completed this round, taken when
+    /// they did, by scene id
+    completed_counts: HashMap<usize, [(u32, u32); 4]>,
+}
+
+impl Evaluation {
+    /// `policies` is a comma-separated list of checkpoints. Reads
+    /// `eval.seeds` (a comma-separated list or an `a..b` range) and
+    /// `eval.episodes`
+    pub fn from_config(policies: &str, config: &Config) -> Result<Self, String> {
+        let seeds = parse_seeds(&config.get("eval.seeds", "0..32".to_string()))?;
+        Ok(Evaluation {
+            policies: policies.split(',').map(|p| p.trim().to_string()).collect(),
+            seeds,
+            episodes: config.get("eval.episodes", 1u64).max(1),
+            results: Vec::new(),
+            policy: 0,
+            next_job: 0,
+            assigned: HashMap::new(),
+            completed_counts: HashMap::new(),
+        })
+    }
+
+    /// Every layout of the suite, in a fixed order
+    pub fn jobs(&self) -> Vec<EvalJob> {
+        self.seeds
+            .iter()
+            .flat_map(|seed| {
+                (0..self.episodes).map(|repeat| EvalJob {
+                    seed: *seed,
+                    repeat,
+                })
+            })
+            .collect()
+    }
+
+    fn of_policy(&self, policy: usize) -> Vec<&EvalEpisode> {
+        self.results.iter().filter(|e| e.policy == policy).collect()
+    }
+
+    /// Prints a policy's summary, returning it as a json object
+    fn report_policy(&self, policy: usize) -> String {
+        let episodes = self.of_policy(policy);
+        let metric = |f: fn(&EvalEpisode) -> Option<f32>| {
+            Summary::of(&episodes.iter().filter_map(|e| f(e)).collect::<Vec<_>>())
+        };
+        let sorted = metric(|e| Some(e.outcome.final_sorted_fraction));
+        let success = metric(|e| Some(e.outcome.success as u8 as f32));
+        let time = metric(|e| e.outcome.time_to_complete);
+        let returns = metric(|e| Some(e.outcome.episode_return));
+        let class_rates = CLASSES
+            .iter()
+            .enumerate()
+            .map(|(i, class)| {
+                let (sorted, total) = episodes.iter().fold((0, 0), |(s, t), e| {
+                    (s + e.class_counts[i].0, t + e.class_counts[i].1)
+                });
+                (*class, (total > 0).then(|| sorted as f32 / total as f32))
+            })
+            .collect::<Vec<_>>();
+
+        println!("Eval of {}:", self.policies[policy]);
+        println!("  final sorted fraction {}", sorted);
+        println!("  success rate          {}", success);
+        println!("  time-to-complete      {}", time);
+        println!("  episode return        {}", returns);
+        for (class, rate) in class_rates.iter() {
+            let name = format!("{:?}", class);
+            let rate = rate.map_or("-".to_string(), |r| format!("{:.3}", r));
+            println!("  {:<8} sorted {}", name, rate);
+        }
+
+        let class_json = class_rates
+            .iter()
+            .map(|(class, rate)| {
+                format!(
+                    "\"{}\": {}",
+                    format!("{:?}", class).to_lowercase(),
+                    rate.map_or("null".to_string(), json_value)
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{{\"policy\": \"{}\", \"final_sorted_fraction\": {}, \"success_rate\": {}, \"time_to_complete\": {}, \"episode_return\": {}, \"class_sorted_rate\": {{{}}}}}",
+            json_escape(&self.policies[policy]),
+            sorted.to_json(),
+            success.to_json(),
+            time.to_json(),
+            returns.to_json(),
+            class_json.join(", ")
+        )
+    }
+
+    /// Pairs `policy`'s episodes with the baseline's on the same layouts,
+    /// printing the difference & returning it as a json object
+    fn compare(&self, policy: usize) -> String {
+        let baseline = self
+            .of_policy(0)
+            .into_iter()
+            .map(|e| (e.job, e.outcome.final_sorted_fraction))
+            .collect::<HashMap<_, _>>();
+        let diffs = self
+            .of_policy(policy)
+            .iter()
+            .filter_map(|e| {
+                let base = baseline.get(&e.job)?;
+                Some(e.outcome.final_sorted_fraction - base)
+            })
+            .collect::<Vec<_>>();
+        let wins = diffs.iter().filter(|d| **d > 0.0).count();
+        let losses = diffs.iter().filter(|d| **d < 0.0).count();
+        let ties = diffs.len() - wins - losses;
+        let diff = Summary::of(&diffs);
+        println!(
+            "{} vs {}: sorted fraction difference {}, {} better / {} tied / {} worse",
+            self.policies[policy], self.policies[0], diff, wins, ties, losses
+        );
+        format!(
+            "{{\"policy\": \"{}\", \"baseline\": \"{}\", \"sorted_fraction_difference\": {}, \"wins\": {}, \"ties\": {}, \"losses\": {}}}",
+            json_escape(&self.policies[policy]),
+            json_escape(&self.policies[0]),
+            diff.to_json(),
+            wins,
+            ties,
+            losses
+        )
+    }
+
+    /// Writes every episode as csv & the summaries as json to `dir`
+    pub fn write_report(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let mut csv = BufWriter::new(File::create(dir.join("episodes.csv"))?);
+        writeln!(
+            csv,
+            "policy,seed,repeat,success,time_to_complete,final_sorted_fraction,episode_return,red,blue,green,yellow"
+        )?;
+        for episode in self.results.iter() {
+            let class_rates = episode
+                .class_counts
+                .iter()
+                .map(|(sorted, total)| {
+                    or_null((*total > 0).then(|| *sorted as f32 / *total as f32))
+                })
+                .collect::<Vec<_>>();
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{}",
+                self.policies[episode.policy],
+                episode.job.seed,
+                episode.job.repeat,
+                episode.outcome.success,
+                or_null(episode.outcome.time_to_complete),
+                episode.outcome.final_sorted_fraction,
+                episode.outcome.episode_return,
+                class_rates.join(",")
+            )?;
+        }
+        csv.flush()?;
+
+        let policies = (0..self.policies.len())
+            .map(|policy| self.report_policy(policy))
+            .collect::<Vec<_>>();
+        let comparisons = (1..self.policies.len())
+            .map(|policy| self.compare(policy))
+            .collect::<Vec<_>>();
+        let seeds = self.seeds.iter().map(u64::to_string).collect::<Vec<_>>();
+        fs::write(
+            dir.join("report.json"),
+            format!(
+                "{{\"seeds\": [{}], \"episodes_per_seed\": {}, \"policies\": [{}], \"comparisons\": [{}]}}\n",
+                seeds.join(", "),
+                self.episodes,
+                policies.join(", "),
+                comparisons.join(", ")
+            ),
+        )
+    }
+}
+
+/// json has no NaN or infinity, those are written as `null`
+fn json_value(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Sorted & total balls of each class
+fn class_counts<'a>(
+    balls: impl Iterator<Item = (&'a Transform, &'a Ball)>,
+    margin: f32,
+) -> [(u32, u32); 4] {
+    let mut counts = [(0, 0); 4];
+    for (transform, ball) in balls {
+        let Some(count) = counts.get_mut(ball.class.to_u8() as usize) else {
+            continue;
+        };
+        count.1 += 1;
+        if ball.correct_quadrant_with_margin(
+            transform.translation.x,
+            transform.translation.z,
+            margin,
+        ) {
+            count.0 += 1;
+        }
+    }
+    counts
+}
+
+/// Hands the next layouts of the suite to the scenes & resets them
+fn assign_jobs(
+    eval: &mut Evaluation,
+    scene_query: &mut Query<&mut BallGameScene>,
+    scene_balls: &mut SceneBalls,
+) {
+    let jobs = eval.jobs();
+    eval.assigned.clear();
+    eval.completed_counts.clear();
+    for mut scene in scene_query.iter_mut() {
+        let scene = &mut *scene;
+        if let Some(job) = jobs.get(eval.next_job) {
+            eval.assigned.insert(scene.id, *job);
+            eval.next_job += 1;
+            scene.seed = episode_seed(job.seed, 0, job.repeat);
+        }
+        for player in scene.players.iter_mut() {
+            player.trajectory.clear();
+            player.demonstration.clear();
+            player.memory.reset();
+        }
+        scene.reward_state.reset();
+        scene.opponent_reward.reset();
+        scene.completed = None;
+        scene_balls.reset(scene);
+    }
+}
+
+/// Loads the first policy, hands every scene to it on the default params
+/// & lays out the suite
+pub fn start_eval(
+    mut eval: ResMut<Evaluation>,
+    mut model: ResMut<ModelResource>,
+    mut scene_query: Query<&mut BallGameScene>,
+    mut scene_balls: SceneBalls,
+) {
+    println!(
+        "Evaluating {} policies on {} seeds x {} episodes",
+        eval.policies.len(),
+        eval.seeds.len(),
+        eval.episodes
+    );
+    *model = ModelResource::new(&eval.policies[0], model.config);
+    for mut scene in scene_query.iter_mut() {
+        scene.controller = ControllerType::AI { training: false };
+        scene.paused_controller = None;
+        // layouts only match between scenes on the same params
+        scene.params = SceneParams::default();
+    }
+    assign_jobs(&mut eval, &mut scene_query, &mut scene_balls);
+}
+
+/// Takes the class counts of scenes as they complete, before their balls
+/// can drift out of their quadrants for the rest of the round
+pub fn snapshot_completed_scenes(
+    mut eval: ResMut<Evaluation>,
+    scene_query: Query<&BallGameScene>,
+    ball_query: Query<(&Transform, &Ball)>,
+    episode_config: Res<EpisodeConfig>,
+) {
+    for scene in scene_query.iter() {
+        if scene.completed.is_none()
+            || !eval.assigned.contains_key(&scene.id)
+            || eval.completed_counts.contains_key(&scene.id)
+        {
+            continue;
+        }
+        let balls = ball_query.iter_many(&scene.game_balls);
+        let counts = class_counts(balls, episode_config.completion_margin);
+        eval.completed_counts.insert(scene.id, counts);
+    }
+}
+
+/// Records the round's episodes & moves on through the suite, then to the
+/// next policy. Writes the report & exits once every policy has played it
+#[allow(clippy::too_many_arguments)]
+pub fn finish_eval_round(
+    mut event_reader: EventReader<SimulationEndedEvent>,
+    mut eval: ResMut<Evaluation>,
+    mut model: ResMut<ModelResource>,
+    mut scene_query: Query<&mut BallGameScene>,
+    mut scene_balls: SceneBalls,
+    episode_config: Res<EpisodeConfig>,
+    run_dir: Res<RunDir>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if event_reader.read().count() == 0 {
+        return;
+    }
+    let margin = episode_config.completion_margin;
+    for scene in scene_query.iter() {
+        let Some(job) = eval.assigned.get(&scene.id).copied() else {
+            continue;
+        };
+        let balls = || {
+            scene
+                .game_balls
+                .iter()
+                .filter_map(|entity| scene_balls.get(*entity))
+        };
+        let episode = EvalEpisode {
+            policy: eval.policy,
+            job,
+            outcome: EpisodeOutcome {
+                scene: scene.id,
+                episode: scene.episode,
+                success: scene.completed.is_some(),
+                time_to_complete: scene.completed,
+                final_sorted_fraction: match scene.completed {
+                    Some(_) => 1.0,
+                    None => sorted_fraction(balls(), margin),
+                },
+                episode_return: scene.reward_state.episode_return,
+                opponent_return: None,
+                human: false,
+                scripted: false,
+            },
+            class_counts: eval
+                .completed_counts
+                .get(&scene.id)
+                .copied()
+                .unwrap_or_else(|| class_counts(balls(), margin)),
+        };
+        eval.results.push(episode);
+    }
+    for mut scene in scene_query.iter_mut() {
+        scene.episode += 1;
+    }
+
+    let total = eval.jobs().len();
+    println!(
+        "Eval: {}/{} episodes of {}",
+        eval.next_job.min(total),
+        total,
+        eval.policies[eval.policy]
+    );
+    if eval.next_job >= total {
+        eval.policy += 1;
+        eval.next_job = 0;
+        if eval.policy == eval.policies.len() {
+            let dir = run_dir.join("eval");
+            match eval.write_report(&dir) {
+                Ok(()) => println!("Eval report written to {}", dir.display()),
+                Err(e) => eprintln!("Failed to write eval report: {}", e),
+            }
+            exit.send(AppExit::Success);
+            return;
+        }
+        *model = ModelResource::new(&eval.policies[eval.policy], model.config);
+    }
+    assign_jobs(&mut eval, &mut scene_query, &mut scene_balls);
+}
@@ -1,4 +1,20 @@
 use bevy::prelude::Event;
 
+use crate::modeling::TrainStats;
+use crate::util::episode::EpisodeOutcome;
+
 #[derive(Event)]
 pub struct SimulationEndedEvent;
+
+/// Sent for every scene when its episode ends
+#[derive(Event)]
+pub struct EpisodeEndedEvent(pub EpisodeOutcome);
+
+/// Sent after the policy is trained on a round of trajectories
+#[derive(Event)]
+pub struct PolicyUpdatedEvent {
+    pub round: u64,
+    pub stats: TrainStats,
+    /// every action taken in the round, as up=1 | down=2 | left=4 | right=8
+    pub actions: Vec<u8>,
+}
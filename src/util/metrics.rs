@@ -0,0 +1,20 @@
+use bevy::prelude::*;
+
+use crate::util::run::RunDir;
+use crate::util::tensorboard::SummaryWriter;
+
+/// TensorBoard event file training metrics are written to
+#[derive(Resource)]
+pub struct Metrics {
+    pub writer: SummaryWriter,
+}
+
+impl Metrics {
+    /// Writes into the run directory, so `tensorboard --logdir runs`
+    /// shows every run side by side
+    pub fn create(run_dir: &RunDir) -> std::io::Result<Self> {
+        Ok(Metrics {
+            writer: SummaryWriter::create(&run_dir.path)?,
+        })
+    }
+}
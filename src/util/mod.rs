@@ -1,6 +1,17 @@
+pub mod checksum;
+pub mod config;
+pub mod curriculum;
+pub mod episode;
+pub mod eval;
 pub mod events;
+pub mod metrics;
 pub mod monitoring;
+pub mod npy;
+pub mod recording;
 pub mod resources;
+pub mod reward;
+pub mod run;
+pub mod tensorboard;
 pub mod systems {
     pub mod gameplay_data;
     pub mod logging;
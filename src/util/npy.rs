@@ -0,0 +1,254 @@
+//! Writers for NumPy `.npy` arrays & uncompressed `.npz` archives
+//!
+//! Arrays are C-ordered & little-endian so `np.load` reads them as-is.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::util::checksum::crc32;
+
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+pub enum NpyData {
+    F32(Vec<f32>),
+    I64(Vec<i64>),
+    U8(Vec<u8>),
+    Bool(Vec<bool>),
+}
+
+/// An n-dimensional array ready to be written
+pub struct NpyArray {
+    pub shape: Vec<usize>,
+    pub data: NpyData,
+}
+
+impl NpyArray {
+    pub fn new(shape: Vec<usize>, data: NpyData) -> Self {
+        let len = match &data {
+            NpyData::F32(v) => v.len(),
+            NpyData::I64(v) => v.len(),
+            NpyData::U8(v) => v.len(),
+            NpyData::Bool(v) => v.len(),
+        };
+        assert_eq!(
+            shape.iter().product::<usize>(),
+            len,
+            "array shape {:?} does not match its {} elements",
+            shape,
+            len
+        );
+        NpyArray { shape, data }
+    }
+
+    fn descr(&self) -> &'static str {
+        match self.data {
+            NpyData::F32(_) => "<f4",
+            NpyData::I64(_) => "<i8",
+            NpyData::U8(_) => "|u1",
+            NpyData::Bool(_) => "|b1",
+        }
+    }
+
+    /// The array in `.npy` format (version 1.0)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let shape = match self.shape.as_slice() {
+            [n] => format!("({},)", n),
+            dims => format!(
+                "({})",
+                dims.iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+            self.descr(),
+            shape
+        );
+        // magic, version & header length take 10 bytes, the whole
+        // preamble is padded to a multiple of 64 & ends in a newline
+        let unpadded = 10 + header.len() + 1;
+        header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+        header.push('\n');
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(NPY_MAGIC);
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        match &self.data {
+            NpyData::F32(v) => v
+                .iter()
+                .for_each(|x| bytes.extend_from_slice(&x.to_le_bytes())),
+            NpyData::I64(v) => v
+                .iter()
+                .for_each(|x| bytes.extend_from_slice(&x.to_le_bytes())),
+            NpyData::U8(v) => bytes.extend_from_slice(v),
+            NpyData::Bool(v) => bytes.extend(v.iter().map(|x| *x as u8)),
+        }
+        bytes
+    }
+}
+
+pub fn write_npy(path: impl AsRef<Path>, array: &NpyArray) -> io::Result<()> {
+    std::fs::write(path, array.to_bytes())
+}
+
+/// Central directory entry for a file already written to the archive
+struct ZipEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes named arrays into an uncompressed (stored) zip, read back
+/// with `np.load(path)["name"]`
+pub struct NpzWriter {
+    writer: BufWriter<File>,
+    entries: Vec<ZipEntry>,
+    offset: u64,
+}
+
+// 1980-01-01 00:00, the earliest date zip can store
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = 0x21;
+
+impl NpzWriter {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(NpzWriter {
+            writer: BufWriter::new(File::create(path)?),
+            entries: Vec::new(),
+            offset: 0,
+        })
+    }
+
+    /// Adds `array` as `<name>.npy`
+    pub fn add(&mut self, name: &str, array: &NpyArray) -> io::Result<()> {
+        let data = array.to_bytes();
+        let name = format!("{}.npy", name);
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "npz archive over 4GB");
+        let entry = ZipEntry {
+            crc: crc32(&data),
+            size: u32::try_from(data.len()).map_err(|_| too_large())?,
+            offset: u32::try_from(self.offset).map_err(|_| too_large())?,
+            name,
+        };
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        header.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        header.extend_from_slice(&0u16.to_le_bytes()); // flags
+        header.extend_from_slice(&0u16.to_le_bytes()); // stored
+        header.extend_from_slice(&DOS_TIME.to_le_bytes());
+        header.extend_from_slice(&DOS_DATE.to_le_bytes());
+        header.extend_from_slice(&entry.crc.to_le_bytes());
+        header.extend_from_slice(&entry.size.to_le_bytes()); // compressed
+        header.extend_from_slice(&entry.size.to_le_bytes()); // uncompressed
+        header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // extra field
+        header.extend_from_slice(entry.name.as_bytes());
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&data)?;
+        self.offset += (header.len() + data.len()) as u64;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes the central directory, after which the archive is complete
+    pub fn finish(mut self) -> io::Result<()> {
+        let mut directory = Vec::new();
+        for entry in self.entries.iter() {
+            directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            directory.extend_from_slice(&20u16.to_le_bytes()); // version made by
+            directory.extend_from_slice(&20u16.to_le_bytes()); // version needed
+            directory.extend_from_slice(&0u16.to_le_bytes()); // flags
+            directory.extend_from_slice(&0u16.to_le_bytes()); // stored
+            directory.extend_from_slice(&DOS_TIME.to_le_bytes());
+            directory.extend_from_slice(&DOS_DATE.to_le_bytes());
+            directory.extend_from_slice(&entry.crc.to_le_bytes());
+            directory.extend_from_slice(&entry.size.to_le_bytes());
+            directory.extend_from_slice(&entry.size.to_le_bytes());
+            directory.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&[0; 12]); // extra, comment, disk, attributes
+            directory.extend_from_slice(&entry.offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let n_entries = self.entries.len() as u16;
+        let mut end = Vec::new();
+        end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        end.extend_from_slice(&[0; 4]); // disk numbers
+        end.extend_from_slice(&n_entries.to_le_bytes());
+        end.extend_from_slice(&n_entries.to_le_bytes());
+        end.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&(self.offset as u32).to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // comment
+
+        self.writer.write_all(&directory)?;
+        self.writer.write_all(&end)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn npy_header_is_what_numpy_writes() {
+        let bytes = NpyArray::new(vec![3], NpyData::I64(vec![1, -2, 3])).to_bytes();
+        let dict = "{'descr': '<i8', 'fortran_order': False, 'shape': (3,), }";
+        // padded with spaces so the data starts 64-byte aligned
+        let mut expected = b"\x93NUMPY\x01\x00".to_vec();
+        expected.extend_from_slice(&118u16.to_le_bytes());
+        expected.extend_from_slice(dict.as_bytes());
+        expected.extend_from_slice(&[b' '; 60]);
+        expected.push(b'\n');
+        assert_eq!(&bytes[..128], expected.as_slice());
+        assert_eq!(bytes.len(), 128 + 3 * 8);
+        assert_eq!(&bytes[128..136], &1i64.to_le_bytes());
+        assert_eq!(&bytes[136..144], &(-2i64).to_le_bytes());
+    }
+
+    #[test]
+    fn npy_shape_lists_every_dimension() {
+        let bytes = NpyArray::new(vec![2, 3], NpyData::F32(vec![0.; 6])).to_bytes();
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.contains("'descr': '<f4'"));
+        assert!(header.contains("'shape': (2, 3)"));
+        assert_eq!((10 + header_len) % 64, 0);
+        assert!(header.ends_with('\n'));
+    }
+
+    #[test]
+    fn npz_stores_arrays_with_their_crcs() {
+        let path = std::env::temp_dir().join(format!("npz_test_{}.npz", std::process::id()));
+        let array = NpyArray::new(vec![2], NpyData::Bool(vec![true, false]));
+        let mut npz = NpzWriter::create(&path).unwrap();
+        npz.add("dones", &array).unwrap();
+        npz.finish().unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let data = array.to_bytes();
+        let u32_at = |i: usize| u32::from_le_bytes(file[i..i + 4].try_into().unwrap());
+        assert_eq!(u32_at(0), 0x0403_4b50);
+        assert_eq!(u32_at(14), crc32(&data));
+        assert_eq!(u32_at(18) as usize, data.len());
+        assert_eq!(&file[30..39], b"dones.npy");
+        assert_eq!(&file[39..39 + data.len()], data.as_slice());
+        // end of central directory: one entry, directory right after the data
+        let end = file.len() - 22;
+        assert_eq!(u32_at(end), 0x0605_4b50);
+        assert_eq!(u16::from_le_bytes([file[end + 10], file[end + 11]]), 1);
+        assert_eq!(u32_at(end + 16) as usize, 39 + data.len());
+    }
+}
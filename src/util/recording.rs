@@ -0,0 +1,466 @@
+//! Binary episode recordings, one file per episode per scene
+//!
+//! All values are little-endian. A file is a header followed by chunks:
+//!
+//! ```text
+//! header: b"BTEP" | version u16 | seed u64 | scene u32
+//!         | config_len u32 | config (utf8, `key = value` lines)
+//!         | n_balls u32 | ball classes [u8; n_balls]
+//! chunk:  tag [u8; 4] | n_steps u32 | payload_len u32 | payload
+//! ```
+//!
+//! `STEP` chunks hold `n_steps` steps of `time f32 | player state
+//! | n_balls ball states | action u8 | reward f32`, where a state is
+//! `x, z, vx, vz` as f32 and the action bits are up=1, down=2, left=4,
+//! right=8. The final chunk is `END\0` with the total number of steps.
+
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::features::ball::BallTag;
+
+pub const MAGIC: &[u8; 4] = b"BTEP";
+pub const FORMAT_VERSION: u16 = 1;
+const STEP_TAG: &[u8; 4] = b"STEP";
+const END_TAG: &[u8; 4] = b"END\0";
+/// steps buffered before a chunk is written
+const STEPS_PER_CHUNK: u32 = 64;
+
+/// Describes the recorded episode
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpisodeHeader {
+    pub version: u16,
+    pub seed: u64,
+    pub scene: u32,
+    pub config: String,
+    /// class of each game ball, in the order ball states are stored
+    pub ball_classes: Vec<BallTag>,
+}
+
+/// Scene-local position & velocity on the ground plane
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BallState {
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepRecord {
+    /// seconds since the episode started
+    pub time: f32,
+    pub player: BallState,
+    pub balls: Vec<BallState>,
+    /// (up, down, left, right)
+    pub action: (bool, bool, bool, bool),
+    pub reward: f32,
+}
+
+fn action_bits(action: (bool, bool, bool, bool)) -> u8 {
+    action.0 as u8 | (action.1 as u8) << 1 | (action.2 as u8) << 2 | (action.3 as u8) << 3
+}
+
+fn bits_action(bits: u8) -> (bool, bool, bool, bool) {
+    (bits & 1 != 0, bits & 2 != 0, bits & 4 != 0, bits & 8 != 0)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Streams an episode to disk a chunk at a time
+pub struct EpisodeWriter {
+    writer: BufWriter<File>,
+    n_balls: usize,
+    chunk: Vec<u8>,
+    chunk_steps: u32,
+    total_steps: u32,
+}
+
+impl EpisodeWriter {
+    /// Fails for any `header.version` other than `FORMAT_VERSION`, the
+    /// only layout this writer produces
+    pub fn create(path: impl AsRef<Path>, header: &EpisodeHeader) -> io::Result<Self> {
+        if header.version != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "can't write format version {}, only {}",
+                    header.version, FORMAT_VERSION
+                ),
+            ));
+        }
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&header.version.to_le_bytes())?;
+        writer.write_all(&header.seed.to_le_bytes())?;
+        writer.write_all(&header.scene.to_le_bytes())?;
+        writer.write_all(&(header.config.len() as u32).to_le_bytes())?;
+        writer.write_all(header.config.as_bytes())?;
+        writer.write_all(&(header.ball_classes.len() as u32).to_le_bytes())?;
+        let classes = header
+            .ball_classes
+            .iter()
+            .map(|c| c.to_u8())
+            .collect::<Vec<u8>>();
+        writer.write_all(&classes)?;
+
+        Ok(EpisodeWriter {
+            writer,
+            n_balls: header.ball_classes.len(),
+            chunk: Vec::new(),
+            chunk_steps: 0,
+            total_steps: 0,
+        })
+    }
+
+    pub fn write_step(&mut self, step: &StepRecord) -> io::Result<()> {
+        if step.balls.len() != self.n_balls {
+            return Err(invalid(
+                "step has a different number of balls than the header",
+            ));
+        }
+        self.chunk.extend_from_slice(&step.time.to_le_bytes());
+        for state in std::iter::once(&step.player).chain(step.balls.iter()) {
+            for v in [
+                state.position.x,
+                state.position.y,
+                state.velocity.x,
+                state.velocity.y,
+            ] {
+                self.chunk.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        self.chunk.push(action_bits(step.action));
+        self.chunk.extend_from_slice(&step.reward.to_le_bytes());
+
+        self.chunk_steps += 1;
+        self.total_steps += 1;
+        if self.chunk_steps >= STEPS_PER_CHUNK {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        if self.chunk_steps == 0 {
+            return Ok(());
+        }
+        self.writer.write_all(STEP_TAG)?;
+        self.writer.write_all(&self.chunk_steps.to_le_bytes())?;
+        self.writer
+            .write_all(&(self.chunk.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&self.chunk)?;
+        self.chunk.clear();
+        self.chunk_steps = 0;
+        Ok(())
+    }
+
+    /// Writes any buffered steps & the end marker
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush_chunk()?;
+        self.writer.write_all(END_TAG)?;
+        self.writer.write_all(&self.total_steps.to_le_bytes())?;
+        self.writer.write_all(&0u32.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+/// Reads a recorded episode back a chunk at a time
+pub struct EpisodeReader {
+    reader: BufReader<File>,
+    pub header: EpisodeHeader,
+    pending: VecDeque<StepRecord>,
+    ended: bool,
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f32(bytes: &[u8], offset: &mut usize) -> f32 {
+    let v = f32::from_le_bytes(bytes[*offset..*offset + 4].try_into().unwrap());
+    *offset += 4;
+    v
+}
+
+impl EpisodeReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not an episode recording"));
+        }
+        let mut buf2 = [0; 2];
+        reader.read_exact(&mut buf2)?;
+        let version = u16::from_le_bytes(buf2);
+        if version > FORMAT_VERSION {
+            return Err(invalid("recording is from a newer format version"));
+        }
+        let mut buf8 = [0; 8];
+        reader.read_exact(&mut buf8)?;
+        let seed = u64::from_le_bytes(buf8);
+        let scene = read_u32(&mut reader)?;
+
+        let mut config = vec![0; read_u32(&mut reader)? as usize];
+        reader.read_exact(&mut config)?;
+        let config = String::from_utf8(config).map_err(|_| invalid("config is not utf8"))?;
+
+        let mut classes = vec![0; read_u32(&mut reader)? as usize];
+        reader.read_exact(&mut classes)?;
+        let ball_classes = classes
+            .iter()
+            .map(|c| BallTag::from_u8(*c).ok_or_else(|| invalid("unknown ball class")))
+            .collect::<io::Result<Vec<BallTag>>>()?;
+
+        Ok(EpisodeReader {
+            reader,
+            header: EpisodeHeader {
+                version,
+                seed,
+                scene,
+                config,
+                ball_classes,
+            },
+            pending: VecDeque::new(),
+            ended: false,
+        })
+    }
+
+    fn step_len(&self) -> usize {
+        4 + (1 + self.header.ball_classes.len()) * 16 + 1 + 4
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let mut tag = [0; 4];
+        match self.reader.read_exact(&mut tag) {
+            // cut off between chunks, e.g. the game closed mid-episode
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.ended = true;
+                return Ok(());
+            }
+            result => result?,
+        }
+        let n_steps = read_u32(&mut self.reader)? as usize;
+        let mut payload = vec![0; read_u32(&mut self.reader)? as usize];
+        self.reader.read_exact(&mut payload)?;
+
+        if &tag == END_TAG {
+            self.ended = true;
+            return Ok(());
+        }
+        if &tag != STEP_TAG {
+            // unknown chunks are skipped so newer writers stay readable
+            return Ok(());
+        }
+        if payload.len() != n_steps * self.step_len() {
+            return Err(invalid("chunk size does not match its step count"));
+        }
+
+        let n_balls = self.header.ball_classes.len();
+        let mut offset = 0;
+        let read_state = |offset: &mut usize| BallState {
+            position: Vec2::new(read_f32(&payload, offset), read_f32(&payload, offset)),
+            velocity: Vec2::new(read_f32(&payload, offset), read_f32(&payload, offset)),
+        };
+        for _ in 0..n_steps {
+            let time = read_f32(&payload, &mut offset);
+            let player = read_state(&mut offset);
+            let balls = (0..n_balls).map(|_| read_state(&mut offset)).collect();
+            let action = bits_action(payload[offset]);
+            offset += 1;
+            let reward = read_f32(&payload, &mut offset);
+            self.pending.push_back(StepRecord {
+                time,
+                player,
+                balls,
+                action,
+                reward,
+            });
+        }
+        Ok(())
+    }
+
+    /// Next step of the episode, `None` once the end marker is reached
+    pub fn next_step(&mut self) -> io::Result<Option<StepRecord>> {
+        while self.pending.is_empty() && !self.ended {
+            self.read_chunk()?;
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    pub fn read_all(mut self) -> io::Result<(EpisodeHeader, Vec<StepRecord>)> {
+        let mut steps = Vec::new();
+        while let Some(step) = self.next_step()? {
+            steps.push(step);
+        }
+        Ok((self.header, steps))
+    }
+}
+
+impl Iterator for EpisodeReader {
+    type Item = io::Result<StepRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_step().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(i: usize, n_balls: usize) -> StepRecord {
+        let v = i as f32;
+        StepRecord {
+            time: v / 60.0,
+            player: BallState {
+                position: Vec2::new(v, -v),
+                velocity: Vec2::new(0.5 * v, 2.0),
+            },
+            balls: (0..n_balls)
+                .map(|b| BallState {
+                    position: Vec2::new(v + b as f32, 1.0),
+                    velocity: Vec2::new(-(b as f32), v),
+                })
+                .collect(),
+            action: bits_action((i % 16) as u8),
+            reward: if i % 3 == 0 { -0.25 } else { v },
+        }
+    }
+
+    fn header() -> EpisodeHeader {
+        EpisodeHeader {
+            version: FORMAT_VERSION,
+            seed: 0xDEAD_BEEF_0123_4567,
+            scene: 17,
+            config: "reward.sorted = 1.5\ncurriculum.enabled = true".to_string(),
+            ball_classes: vec![BallTag::Red, BallTag::Yellow, BallTag::Green, BallTag::Blue],
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("recording_{}_{}.btep", name, std::process::id()))
+    }
+
+    /// Records `steps` & returns the file's bytes
+    fn record(name: &str, header: &EpisodeHeader, steps: &[StepRecord]) -> Vec<u8> {
+        let path = temp_path(name);
+        let mut writer = EpisodeWriter::create(&path, header).unwrap();
+        for step in steps.iter() {
+            writer.write_step(step).unwrap();
+        }
+        writer.finish().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    /// Reads back a recording made of `bytes`, one step at a time
+    fn play(name: &str, bytes: &[u8]) -> (EpisodeHeader, Vec<io::Result<StepRecord>>) {
+        let path = temp_path(name);
+        std::fs::write(&path, bytes).unwrap();
+        let reader = EpisodeReader::open(&path).unwrap();
+        let header = reader.header.clone();
+        let steps = reader.collect();
+        std::fs::remove_file(&path).unwrap();
+        (header, steps)
+    }
+
+    fn header_len(header: &EpisodeHeader) -> usize {
+        4 + 2 + 8 + 4 + 4 + header.config.len() + 4 + header.ball_classes.len()
+    }
+
+    fn chunk_len(header: &EpisodeHeader) -> usize {
+        12 + STEPS_PER_CHUNK as usize * (4 + (1 + header.ball_classes.len()) * 16 + 1 + 4)
+    }
+
+    #[test]
+    fn round_trips_header_and_steps_across_chunks() {
+        let header = header();
+        // two full chunks & a partial one
+        let steps = (0..2 * STEPS_PER_CHUNK as usize + 5)
+            .map(|i| step(i, header.ball_classes.len()))
+            .collect::<Vec<_>>();
+        let bytes = record("round_trip", &header, &steps);
+
+        let (read_header, read_steps) = play("round_trip", &bytes);
+        assert_eq!(read_header, header);
+        let read_steps = read_steps
+            .into_iter()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read_steps, steps);
+    }
+
+    #[test]
+    fn only_writes_its_own_format_version() {
+        let path = temp_path("newer_version");
+        let header = EpisodeHeader {
+            version: FORMAT_VERSION + 1,
+            ..header()
+        };
+        let error = EpisodeWriter::create(&path, &header).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn skips_unknown_chunks() {
+        let header = header();
+        let steps = (0..STEPS_PER_CHUNK as usize + 3)
+            .map(|i| step(i, header.ball_classes.len()))
+            .collect::<Vec<_>>();
+        let mut bytes = record("unknown_chunk", &header, &steps);
+        // a chunk a newer writer might add, between the two step chunks
+        let at = header_len(&header) + chunk_len(&header);
+        let mut unknown = b"NOTE".to_vec();
+        unknown.extend_from_slice(&0u32.to_le_bytes());
+        unknown.extend_from_slice(&3u32.to_le_bytes());
+        unknown.extend_from_slice(b"abc");
+        bytes.splice(at..at, unknown);
+
+        let (_, read_steps) = play("unknown_chunk", &bytes);
+        let read_steps = read_steps
+            .into_iter()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read_steps, steps);
+    }
+
+    #[test]
+    fn reads_up_to_where_a_file_was_cut_off() {
+        let header = header();
+        let steps = (0..2 * STEPS_PER_CHUNK as usize)
+            .map(|i| step(i, header.ball_classes.len()))
+            .collect::<Vec<_>>();
+        let bytes = record("cut_off", &header, &steps);
+        let first_chunk_end = header_len(&header) + chunk_len(&header);
+
+        // between chunks, e.g. the game closed mid-episode: the steps so
+        // far read back & the episode just ends
+        let (_, read_steps) = play("cut_between", &bytes[..first_chunk_end]);
+        let read_steps = read_steps
+            .into_iter()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read_steps, steps[..STEPS_PER_CHUNK as usize]);
+
+        // partway through a chunk: the whole chunks read back, then an error
+        let (_, read_steps) = play("cut_within", &bytes[..first_chunk_end + 20]);
+        assert_eq!(read_steps.len(), STEPS_PER_CHUNK as usize + 1);
+        for (read, step) in read_steps.iter().zip(steps.iter()) {
+            assert_eq!(read.as_ref().unwrap(), step);
+        }
+        assert!(read_steps.last().unwrap().is_err());
+    }
+}
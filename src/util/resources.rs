@@ -1,13 +1,36 @@
-use bevy::prelude::{Query, ResMut, Resource, Timer, Transform, With};
+use bevy::prelude::{Query, Res, ResMut, Resource, Timer, Transform, Vec2, With};
 
 use crate::features::ball::Ball;
+use crate::features::versus::{Versus, VersusGoal, LEARNER_TEAM};
+use crate::scenes::ball_game_scene::BallGameScene;
+use crate::util::reward::{RewardFn, RewardInput};
 
 #[derive(Resource, Default, Debug)]
 pub struct ProgramInputs {
     pub headless: bool,
     pub ai_control: bool,
+    pub image_obs: bool,
+    pub frame_stack: usize,
+    pub recurrent: bool,
+    pub record: bool,
+    pub export_npz: bool,
+    pub tensorboard: bool,
+    pub demo: bool,
+    pub dagger: bool,
+    /// player balls per scene
+    pub players: usize,
+    /// give every player of a team its own policy instead of sharing one
+    pub independent_policies: bool,
+    /// a second team per scene, played by a frozen opponent
+    pub versus: bool,
+    /// versus against a league of rated snapshots, implies `versus`
+    pub league: bool,
 }
 
+/// Seed every scene's layouts are derived from
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct WorldSeed(pub u64);
+
 #[derive(Resource)]
 pub struct SimulationTimer {
     pub timer: Timer,
@@ -41,7 +64,60 @@ impl WorldState {
 pub fn update_world_state(
     mut state: ResMut<WorldState>,
     ball_query: Query<(&Transform, &Ball), With<Ball>>,
+    mut scene_query: Query<&mut BallGameScene>,
+    reward_fn: Res<RewardFn>,
+    versus: Option<Res<Versus>>,
 ) {
+    // per scene reward for training
+    let flat = |t: &Transform| Vec2::new(t.translation.x, t.translation.z);
+    for mut scene in scene_query.iter_mut() {
+        if scene.completed.is_some() {
+            continue;
+        }
+        let scene = &mut *scene;
+        let balls = ball_query
+            .iter_many(&scene.game_balls)
+            .map(|(transform, ball)| (flat(transform), ball))
+            .collect::<Vec<_>>();
+        // each team shares a reward, whichever of its trajectories took
+        // this frame's step gets it
+        let mut rewards = Vec::new();
+        for team in 0..scene.teams.count.max(1) {
+            let players = ball_query
+                .iter_many(
+                    scene
+                        .players
+                        .iter()
+                        .filter(|player| player.team == team)
+                        .map(|player| player.ball),
+                )
+                .map(|(transform, ball)| (flat(transform), ball.radius))
+                .collect::<Vec<_>>();
+            let scored = balls
+                .iter()
+                .filter(|(_, ball)| versus.as_ref().map_or(true, |v| v.scores(team, ball.class)))
+                .copied()
+                .collect::<Vec<_>>();
+            let input = RewardInput {
+                players: &players,
+                balls: &scored,
+            };
+            let reward = match versus.as_ref().map(|v| v.goal) {
+                _ if team == LEARNER_TEAM => reward_fn.evaluate(&input, &mut scene.reward_state),
+                Some(VersusGoal::Disrupt) => {
+                    scene.opponent_reward.add(-rewards[LEARNER_TEAM]);
+                    -rewards[LEARNER_TEAM]
+                }
+                _ => reward_fn.evaluate(&input, &mut scene.opponent_reward),
+            };
+            rewards.push(reward);
+        }
+        for player in scene.players.iter_mut() {
+            player.trajectory.reward_pending(rewards[player.team]);
+            player.demonstration.reward_pending(rewards[player.team]);
+        }
+    }
+
     let mut reward = 0.;
     for (transform, ball) in ball_query.iter() {
         if ball.correct_quadrant(transform.translation.x, transform.translation.z) {
@@ -0,0 +1,199 @@
+use bevy::prelude::*;
+
+use crate::features::ball::Ball;
+use crate::scenes::ball_game_scene::ARENA_HALF_SIZE;
+use crate::util::config::Config;
+
+/// A single component of the reward, weighted & summed by `RewardFn`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RewardTerm {
+    /// number of balls in their correct quadrant
+    SortedCount,
+    /// change in the number of correctly placed balls since last step
+    SortedDelta,
+    /// potential-based shaping on total distance of balls to their quadrants
+    DistanceShaping,
+    /// negative distance from the player to the nearest unsorted ball
+    PlayerToUnsorted,
+    /// constant -1 every step
+    TimePenalty,
+    /// -1 while the player is pressed against a wall
+    PlayerWallPenalty,
+    /// minus the fraction of game balls pressed against a wall
+    BallWallPenalty,
+}
+
+impl RewardTerm {
+    pub const ALL: [RewardTerm; 7] = [
+        RewardTerm::SortedCount,
+        RewardTerm::SortedDelta,
+        RewardTerm::DistanceShaping,
+        RewardTerm::PlayerToUnsorted,
+        RewardTerm::TimePenalty,
+        RewardTerm::PlayerWallPenalty,
+        RewardTerm::BallWallPenalty,
+    ];
+
+    /// name used for config keys (`reward.<name>`) & logging
+    pub fn name(&self) -> &'static str {
+        match self {
+            RewardTerm::SortedCount => "sorted_count",
+            RewardTerm::SortedDelta => "sorted_delta",
+            RewardTerm::DistanceShaping => "distance_shaping",
+            RewardTerm::PlayerToUnsorted => "player_to_unsorted",
+            RewardTerm::TimePenalty => "time_penalty",
+            RewardTerm::PlayerWallPenalty => "player_wall_penalty",
+            RewardTerm::BallWallPenalty => "ball_wall_penalty",
+        }
+    }
+}
+
+/// Scene-local snapshot of a single step
+pub struct RewardInput<'a> {
+    /// position & radius of each of the scene's player balls
+    pub players: &'a [(Vec2, f32)],
+    pub balls: &'a [(Vec2, &'a Ball)],
+}
+
+/// Per scene bookkeeping between steps & totals for the current episode
+#[derive(Default)]
+pub struct RewardState {
+    prev_sorted: Option<usize>,
+    prev_potential: Option<f32>,
+    /// undiscounted sum of each weighted term, in `RewardFn::terms` order
+    pub episode_terms: Vec<f32>,
+    pub episode_return: f32,
+    /// reward given on the most recent step
+    pub last: f32,
+}
+
+impl RewardState {
+    pub fn reset(&mut self) {
+        *self = RewardState::default();
+    }
+
+    /// Books a reward worked out from another team's, without its terms
+    pub fn add(&mut self, reward: f32) {
+        self.episode_return += reward;
+        self.last = reward;
+    }
+}
+
+/// Weighted sum of reward terms
+#[derive(Resource, Clone, Debug)]
+pub struct RewardFn {
+    pub terms: Vec<(RewardTerm, f32)>,
+    /// discount used by the potential-based shaping term
+    pub gamma: f32,
+    /// how close to a wall counts as touching it
+    pub wall_margin: f32,
+}
+
+impl Default for RewardFn {
+    /// +1 per correctly placed ball per step
+    fn default() -> Self {
+        RewardFn {
+            terms: vec![(RewardTerm::SortedCount, 1.0)],
+            gamma: 0.99,
+            wall_margin: 0.25,
+        }
+    }
+}
+
+impl RewardFn {
+    /// Reads `reward.<term> = <weight>` entries; terms with no weight are left out
+    pub fn from_config(config: &Config) -> Self {
+        let default = RewardFn::default();
+        let terms = RewardTerm::ALL
+            .iter()
+            .filter_map(|term| {
+                let default_weight = match default.terms.iter().find(|(t, _)| t == term) {
+                    Some((_, w)) => *w,
+                    None => 0.0,
+                };
+                let weight = config.get(&format!("reward.{}", term.name()), default_weight);
+                (weight != 0.0).then_some((*term, weight))
+            })
+            .collect();
+        RewardFn {
+            terms,
+            gamma: config.get("reward.gamma", default.gamma),
+            wall_margin: config.get("reward.wall_margin", default.wall_margin),
+        }
+    }
+
+    fn touching_wall(&self, position: Vec2, radius: f32) -> bool {
+        let limit = ARENA_HALF_SIZE - radius - self.wall_margin;
+        position.x.abs() >= limit || position.y.abs() >= limit
+    }
+
+    /// Scores a step, updating the scene's reward state
+    pub fn evaluate(&self, input: &RewardInput, state: &mut RewardState) -> f32 {
+        let is_sorted = |(p, ball): &&(Vec2, &Ball)| ball.correct_quadrant(p.x, p.y);
+        let sorted = input.balls.iter().filter(is_sorted).count();
+        let potential = -input
+            .balls
+            .iter()
+            .map(|(p, ball)| ball.distance_to_quadrant(p.x, p.y))
+            .sum::<f32>();
+
+        if state.episode_terms.len() != self.terms.len() {
+            state.episode_terms = vec![0.0; self.terms.len()];
+        }
+        let mut reward = 0.0;
+        for (i, (term, weight)) in self.terms.iter().enumerate() {
+            let value = match term {
+                RewardTerm::SortedCount => sorted as f32,
+                RewardTerm::SortedDelta => match state.prev_sorted {
+                    Some(prev) => sorted as f32 - prev as f32,
+                    None => 0.0,
+                },
+                RewardTerm::DistanceShaping => match state.prev_potential {
+                    Some(prev) => self.gamma * potential - prev,
+                    None => 0.0,
+                },
+                // team terms are averaged over the players
+                RewardTerm::PlayerToUnsorted => {
+                    let nearest = |player: Vec2| {
+                        input
+                            .balls
+                            .iter()
+                            .filter(|b| !is_sorted(b))
+                            .map(|(p, _)| p.distance(player))
+                            .fold(None, |min: Option<f32>, d| {
+                                Some(min.map_or(d, |m| m.min(d)))
+                            })
+                            .unwrap_or(0.0)
+                    };
+                    -input.players.iter().map(|(p, _)| nearest(*p)).sum::<f32>()
+                        / input.players.len().max(1) as f32
+                }
+                RewardTerm::TimePenalty => -1.0,
+                RewardTerm::PlayerWallPenalty => {
+                    let touching = input
+                        .players
+                        .iter()
+                        .filter(|(p, radius)| self.touching_wall(*p, *radius))
+                        .count();
+                    -(touching as f32) / input.players.len().max(1) as f32
+                }
+                RewardTerm::BallWallPenalty => {
+                    let touching = input
+                        .balls
+                        .iter()
+                        .filter(|(p, ball)| self.touching_wall(*p, ball.radius))
+                        .count();
+                    -(touching as f32) / input.balls.len().max(1) as f32
+                }
+            };
+            state.episode_terms[i] += weight * value;
+            reward += weight * value;
+        }
+
+        state.prev_sorted = Some(sorted);
+        state.prev_potential = Some(potential);
+        state.episode_return += reward;
+        state.last = reward;
+        reward
+    }
+}
@@ -0,0 +1,195 @@
+//! Per-run output directory & its structured logs
+//!
+//! Every run writes to `runs/<date>_<time>/` (UTC):
+//!
+//! ```text
+//! config.txt       resolved config, defaults included
+//! metrics.jsonl    one record per episode per scene & per update
+//! episodes.csv     the episode records as csv
+//! updates.csv      the update records as csv
+//! checkpoints/     policy snapshots, `round<n>.pt`
+//! recordings/      episode recordings with `--record`
+//! exports/         trajectory dumps with `--export-npz`
+//! ```
+
+use bevy::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::modeling::TrainStats;
+use crate::util::config::Config;
+use crate::util::episode::EpisodeOutcome;
+
+const EPISODE_COLUMNS: &str =
+    "scene,episode,human,scripted,success,time_to_complete,final_sorted_fraction,episode_return,opponent_return";
+const UPDATE_COLUMNS: &str =
+    "round,steps,policy_loss,entropy,value_loss,grad_norm,learning_rate,steps_per_second";
+
+/// `YYYY-MM-DD_HH-MM-SS` for a unix timestamp
+fn timestamp(secs: u64) -> String {
+    // days since the epoch to a civil date, from Howard Hinnant's algorithm
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    let time = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Where everything a run produces is written
+#[derive(Resource, Clone, Debug)]
+pub struct RunDir {
+    pub path: PathBuf,
+    /// rounds between policy checkpoints
+    pub checkpoint_every: u64,
+}
+
+impl RunDir {
+    /// Makes a new directory under `run.dir` (`runs` by default), adding a
+    /// suffix if another run started in the same second
+    pub fn create(config: &Config) -> io::Result<Self> {
+        let base = PathBuf::from(config.get("run.dir", "runs".to_string()));
+        fs::create_dir_all(&base)?;
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let name = timestamp(secs);
+        let mut path = base.join(&name);
+        let mut suffix = 1;
+        loop {
+            match fs::create_dir(&path) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    path = base.join(format!("{}-{}", name, suffix));
+                    suffix += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        println!("Writing run output to {}", path.display());
+        Ok(RunDir {
+            path,
+            checkpoint_every: config.get("run.checkpoint_every", 10u64).max(1),
+        })
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+
+    /// Saves the settings the run used, with the command line as a comment
+    pub fn write_config(&self, config: &Config, args: &[String]) -> io::Result<()> {
+        let text = format!("# {}\n{}", args.join(" "), config.resolved());
+        fs::write(self.join("config.txt"), text)
+    }
+}
+
+fn csv_writer(path: PathBuf, columns: &str) -> io::Result<BufWriter<File>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}", columns)?;
+    Ok(writer)
+}
+
+/// `null` for missing values, as in both json & pandas' csv reader
+pub(crate) fn or_null(value: Option<f32>) -> String {
+    value.map_or("null".to_string(), |v| v.to_string())
+}
+
+/// Episode & update records, written to `metrics.jsonl` and a csv per kind
+#[derive(Resource)]
+pub struct RunLog {
+    jsonl: BufWriter<File>,
+    episodes: BufWriter<File>,
+    updates: BufWriter<File>,
+}
+
+impl RunLog {
+    pub fn create(run_dir: &RunDir) -> io::Result<Self> {
+        Ok(RunLog {
+            jsonl: BufWriter::new(File::create(run_dir.join("metrics.jsonl"))?),
+            episodes: csv_writer(run_dir.join("episodes.csv"), EPISODE_COLUMNS)?,
+            updates: csv_writer(run_dir.join("updates.csv"), UPDATE_COLUMNS)?,
+        })
+    }
+
+    pub fn episode(&mut self, outcome: &EpisodeOutcome) -> io::Result<()> {
+        let values = [
+            outcome.scene.to_string(),
+            outcome.episode.to_string(),
+            outcome.human.to_string(),
+            outcome.scripted.to_string(),
+            outcome.success.to_string(),
+            or_null(outcome.time_to_complete),
+            outcome.final_sorted_fraction.to_string(),
+            outcome.episode_return.to_string(),
+            or_null(outcome.opponent_return),
+        ];
+        self.write_json("episode", EPISODE_COLUMNS, &values)?;
+        writeln!(self.episodes, "{}", values.join(","))
+    }
+
+    pub fn update(
+        &mut self,
+        round: u64,
+        stats: &TrainStats,
+        steps_per_second: f32,
+    ) -> io::Result<()> {
+        let values = [
+            round.to_string(),
+            stats.steps.to_string(),
+            stats.policy_loss.to_string(),
+            stats.entropy.to_string(),
+            or_null(stats.value_loss),
+            stats.grad_norm.to_string(),
+            stats.learning_rate.to_string(),
+            steps_per_second.to_string(),
+        ];
+        self.write_json("update", UPDATE_COLUMNS, &values)?;
+        writeln!(self.updates, "{}", values.join(","))
+    }
+
+    /// Values are numbers, bools or `null`, so none need quoting
+    fn write_json(&mut self, kind: &str, columns: &str, values: &[String]) -> io::Result<()> {
+        let fields = columns
+            .split(',')
+            .zip(values)
+            .map(|(column, value)| format!("\"{}\": {}", column, json_number(value)))
+            .collect::<Vec<String>>();
+        writeln!(
+            self.jsonl,
+            "{{\"kind\": \"{}\", {}}}",
+            kind,
+            fields.join(", ")
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.jsonl.flush()?;
+        self.episodes.flush()?;
+        self.updates.flush()
+    }
+}
+
+/// json has no NaN or infinity, those are written as `null`
+fn json_number(value: &str) -> &str {
+    match value {
+        "NaN" | "inf" | "-inf" => "null",
+        v => v,
+    }
+}
@@ -3,13 +3,19 @@ use bevy_rapier3d::prelude::*;
 
 use tch::Tensor;
 
-use crate::features::ball::{Ball, ControllableBall};
-use crate::modeling::{learn, ModelResource, Trajectory};
-use crate::scenes::BallGameScene::reset_scene;
-use crate::util::logging::AggBallPositions;
+use crate::features::ball::{Ball, BallTag};
+use crate::features::player_controllers::ControllerType;
+use crate::modeling::distributed::RolloutWorker;
+use crate::modeling::export::{export_trajectories, SceneTrajectory};
+use crate::modeling::{learn, ModelResource, TeamPolicies, Trajectory};
+use crate::scenes::BallGameScene::{episode_seed, BallGameScene, Player, SceneBalls};
+use crate::util::curriculum::Curriculum;
+use crate::util::episode::*;
+use crate::util::reward::RewardFn;
 use crate::util::{
-    events::SimulationEndedEvent,
-    resources::{SimulationTimer, WorldState},
+    events::{EpisodeEndedEvent, PolicyUpdatedEvent, SimulationEndedEvent},
+    resources::{ProgramInputs, SimulationTimer, WorldSeed, WorldState},
+    run::RunDir,
 };
 
 /// Collects model input
@@ -38,36 +44,264 @@ pub fn check_simulation_end(
     }
 }
 
+/// A scene's episode ends early once every ball is sorted; the scene
+/// then sits idle until the next reset
+pub fn check_scene_complete(
+    mut scene_query: Query<&mut BallGameScene>,
+    ball_query: Query<(&Transform, &Ball)>,
+    episode_config: Res<EpisodeConfig>,
+    simulation_timer: Res<SimulationTimer>,
+) {
+    for mut scene in scene_query.iter_mut() {
+        if scene.completed.is_some() {
+            continue;
+        }
+        let balls = ball_query.iter_many(&scene.game_balls);
+        if sorted_fraction(balls, episode_config.completion_margin) >= 1.0 {
+            let t = simulation_timer.timer.elapsed_secs();
+            scene.completed = Some(t);
+            for player in scene.players.iter_mut() {
+                player.trajectory.end_episode();
+            }
+            if let ControllerType::Keyboard = scene.controller {
+                println!("Scene {} sorted in {:.2}s!", scene.id, t);
+            }
+        }
+    }
+}
+
 /// When a simulation ends we train the model and restart
+#[allow(clippy::too_many_arguments)]
 pub fn on_simulation_end(
     mut event_reader: EventReader<SimulationEndedEvent>,
     mut model: ResMut<ModelResource>,
-    trajectories: Query<&Trajectory, With<Trajectory>>,
-    ball_positions: Res<AggBallPositions>,
+    mut team: Option<ResMut<TeamPolicies>>,
+    mut worker: Option<ResMut<RolloutWorker>>,
+    mut scene_query: Query<&mut BallGameScene>,
     mut world_state: ResMut<WorldState>,
-    ball_query: Query<&Ball>,
-    param_set: ParamSet<(
-        Query<(&mut Velocity, &mut Transform), With<Ball>>,
-        Query<(&mut Velocity, &mut Transform), With<ControllableBall>>,
-    )>,
+    reward_fn: Res<RewardFn>,
+    episode_config: Res<EpisodeConfig>,
+    mut episode_writer: EventWriter<EpisodeEndedEvent>,
+    mut policy_writer: EventWriter<PolicyUpdatedEvent>,
+    mut curriculum: Option<ResMut<Curriculum>>,
+    mut scene_balls: SceneBalls,
+    world_seed: Res<WorldSeed>,
+    program_inputs: Res<ProgramInputs>,
+    run_dir: Res<RunDir>,
 ) {
     if event_reader.read().into_iter().count() == 0 {
         return;
     }
-    // save the ball positions to a file
-    let save_result = ball_positions.save_to_file("ball_positions.txt", &ball_query);
-    match save_result {
-        Ok(_) => println!("Ball positions saved successfully."),
-        Err(e) => eprintln!("Failed to save ball positions: {}", e),
+    // close out each scene's recording
+    for mut scene in scene_query.iter_mut() {
+        if let Some(recorder) = scene.recorder.take() {
+            if let Err(e) = recorder.finish() {
+                eprintln!("Failed to save recording of scene {}: {}", scene.id, e);
+            }
+        }
     }
 
     // score stuff
     println!("Final Score: {:?}", world_state);
     world_state.reset();
 
-    // Reset scene (or exit)
-    reset_scene(param_set);
+    // record how each scene's episode went, leaving out league games the
+    // learner didn't play in
+    let mut outcomes = Vec::new();
+    for scene in scene_query.iter() {
+        if !scene.matchup.has_learner() {
+            continue;
+        }
+        let balls = scene
+            .game_balls
+            .iter()
+            .filter_map(|entity| scene_balls.get(*entity));
+        outcomes.push(EpisodeOutcome {
+            scene: scene.id,
+            episode: scene.episode,
+            success: scene.completed.is_some(),
+            time_to_complete: scene.completed,
+            final_sorted_fraction: match scene.completed {
+                Some(_) => 1.0,
+                None => sorted_fraction(balls, episode_config.completion_margin),
+            },
+            episode_return: scene.reward_state.episode_return,
+            opponent_return: (scene.teams.count > 1)
+                .then_some(scene.opponent_reward.episode_return),
+            human: matches!(scene.controller, ControllerType::Keyboard),
+            scripted: matches!(scene.controller, ControllerType::Scripted { .. }),
+        });
+    }
+    let (human, ai): (Vec<_>, Vec<_>) = outcomes.iter().cloned().partition(|o| o.human);
+    let (scripted, ai): (Vec<_>, Vec<_>) = ai.into_iter().partition(|o| o.scripted);
+    print_outcome_summary("AI episodes", &ai);
+    print_outcome_summary("Human episodes", &human);
+    print_outcome_summary("Scripted episodes", &scripted);
+    print_versus_summary(&outcomes);
+
+    // move scenes through the curriculum
+    if let Some(curriculum) = curriculum.as_mut() {
+        for outcome in outcomes.iter() {
+            if let Some(level) = curriculum.record(outcome) {
+                println!(
+                    "Scene {} advanced to curriculum level {}",
+                    outcome.scene, level
+                );
+            }
+        }
+        for mut scene in scene_query.iter_mut() {
+            scene.params = curriculum.params_for(scene.id);
+        }
+    }
+    episode_writer.send_batch(outcomes.into_iter().map(EpisodeEndedEvent));
+
+    // ball classes have to be read before the scenes are re-laid out
+    let round = scene_query
+        .iter()
+        .map(|scene| scene.episode)
+        .max()
+        .unwrap_or(0);
+    let ball_classes = scene_query
+        .iter()
+        .map(|scene| {
+            scene
+                .game_balls
+                .iter()
+                .filter_map(|entity| scene_balls.get(*entity))
+                .map(|(_, ball)| ball.class)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // Reset scene with the next episode's seed (or exit)
+    for mut scene in scene_query.iter_mut() {
+        scene.episode += 1;
+        scene.seed = episode_seed(world_seed.0, scene.id, scene.episode);
+        scene_balls.reset(&mut scene);
+    }
     // writer.send(AppExit::Success);
 
-    learn(&mut model, trajectories.iter().collect());
+    // log each reward term, averaged over the learner's scenes
+    let n_scenes = scene_query
+        .iter()
+        .filter(|scene| scene.matchup.has_learner())
+        .count()
+        .max(1) as f32;
+    for (i, (term, _)) in reward_fn.terms.iter().enumerate() {
+        let total = scene_query
+            .iter()
+            .filter(|scene| scene.matchup.has_learner())
+            .filter_map(|scene| scene.reward_state.episode_terms.get(i))
+            .sum::<f32>();
+        println!("Reward term {}: {:.3}", term.name(), total / n_scenes);
+    }
+
+    // close out the episode for every scene
+    for mut scene in scene_query.iter_mut() {
+        for player in scene.players.iter_mut() {
+            player.trajectory.end_episode();
+            player.demonstration.end_episode();
+            player.memory.reset();
+        }
+        scene.reward_state.reset();
+        scene.opponent_reward.reset();
+        scene.completed = None;
+    }
+
+    // human & expert demonstrations are saved every round they're collected
+    let demonstrations = player_trajectories(scene_query.iter(), &ball_classes, |player| {
+        &player.demonstration
+    })
+    .into_iter()
+    .filter(|scene| !scene.trajectory.is_empty())
+    .collect::<Vec<_>>();
+    if !demonstrations.is_empty() {
+        let path = run_dir.join(format!("demonstrations/round{:05}.npz", round));
+        if let Err(e) = export_trajectories(&path, &demonstrations) {
+            eprintln!("Failed to save demonstrations to {}: {}", path.display(), e);
+        }
+    }
+
+    if program_inputs.export_npz {
+        let scenes = player_trajectories(scene_query.iter(), &ball_classes, |player| {
+            &player.trajectory
+        });
+        let path = run_dir.join(format!("exports/round{:05}.npz", round));
+        if let Err(e) = export_trajectories(&path, &scenes) {
+            eprintln!("Failed to export trajectories to {}: {}", path.display(), e);
+        }
+    }
+
+    let actions = scene_query
+        .iter()
+        .flat_map(|scene| scene.players.iter())
+        .flat_map(|player| player.trajectory.action.iter())
+        .map(|action| {
+            action
+                .iter()
+                .enumerate()
+                .fold(0u8, |bits, (i, a)| bits | (((*a > 0.5) as u8) << i))
+        })
+        .collect();
+    // a shared policy learns from every player, independent ones from their own
+    let n_policies = 1 + team.as_ref().map_or(0, |team| team.models.len());
+    let trajectories_of = |policy: usize| {
+        scene_query
+            .iter()
+            .flat_map(|scene| scene.players.iter().enumerate())
+            .filter(|(k, _)| n_policies == 1 || *k == policy)
+            .map(|(_, player)| &player.trajectory)
+            .collect::<Vec<_>>()
+    };
+    // a worker hands its steps to the learner & takes the weights it sends back
+    let stats = match worker.as_mut() {
+        Some(worker) => worker
+            .exchange(&mut model, trajectories_of(0))
+            .unwrap_or_else(|e| {
+                eprintln!("Worker {}: lost the learner: {}", worker.index, e);
+                std::process::exit(1)
+            }),
+        None => learn(&mut model, trajectories_of(0)),
+    };
+    if let Some(team) = team.as_mut() {
+        for (k, model) in team.models.iter_mut().enumerate() {
+            let stats = learn(model, trajectories_of(k + 1));
+            println!("Player {} policy loss: {:.4}", k + 1, stats.policy_loss);
+        }
+    }
+    policy_writer.send(PolicyUpdatedEvent {
+        round,
+        stats,
+        actions,
+    });
+    for mut scene in scene_query.iter_mut() {
+        for player in scene.players.iter_mut() {
+            player.trajectory.clear();
+            player.demonstration.clear();
+        }
+    }
+}
+
+/// One `SceneTrajectory` per player of every scene, `which` picking
+/// the player's trajectory
+fn player_trajectories<'a>(
+    scenes: impl Iterator<Item = &'a BallGameScene>,
+    ball_classes: &[Vec<BallTag>],
+    which: fn(&Player) -> &Trajectory,
+) -> Vec<SceneTrajectory<'a>> {
+    scenes
+        .zip(ball_classes.iter())
+        .flat_map(|(scene, ball_classes)| {
+            scene
+                .players
+                .iter()
+                .enumerate()
+                .map(move |(k, player)| SceneTrajectory {
+                    scene: scene.id,
+                    player: k,
+                    trajectory: which(player),
+                    ball_classes: ball_classes.clone(),
+                })
+        })
+        .collect()
 }
@@ -1,49 +1,320 @@
 use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
 
 use crate::features::ball::*;
+use crate::scenes::ball_game_scene::BallGameScene;
+use crate::util::config::Config;
+use crate::util::episode::EpisodeOutcome;
+use crate::util::events::{EpisodeEndedEvent, PolicyUpdatedEvent, SimulationEndedEvent};
+use crate::util::metrics::Metrics;
+use crate::util::recording::*;
+use crate::util::resources::SimulationTimer;
+use crate::util::run::{RunDir, RunLog};
+use crate::util::tensorboard::SummaryWriter;
 
-#[derive(Resource, Default)]
+/// Ball positions of the current episode, flushed to the run's
+/// `positions/` when the episode ends so memory stays bounded
+#[derive(Resource)]
 pub struct AggBallPositions {
-    positions: std::collections::HashMap<Entity, Vec<(f32, f32)>>,
+    positions: HashMap<Entity, Vec<(f32, f32)>>,
+    /// frames between samples
+    pub stride: usize,
+    /// scenes to track, every scene when `None`
+    pub scenes: Option<HashSet<usize>>,
+    frame: usize,
 }
+
 impl AggBallPositions {
-    pub fn save_to_file(
-        &self,
-        file_path: &str,
-        ball_classes: &Query<&Ball>,
+    /// Reads `positions.stride` & `positions.scenes`, a comma separated
+    /// list of scene ids or `all`
+    pub fn from_config(config: &Config) -> Self {
+        let scenes = config.get("positions.scenes", "all".to_string());
+        AggBallPositions {
+            positions: HashMap::new(),
+            stride: config.get("positions.stride", 1).max(1),
+            scenes: match scenes.trim() {
+                "all" => None,
+                ids => Some(
+                    ids.split(',')
+                        .filter_map(|id| id.trim().parse().ok())
+                        .collect(),
+                ),
+            },
+            frame: 0,
+        }
+    }
+
+    pub fn tracks(&self, scene: usize) -> bool {
+        self.scenes.as_ref().map_or(true, |s| s.contains(&scene))
+    }
+
+    /// Writes the scene's balls in the `Class:` / `x,z` / `---` format
+    /// `test.ipynb` reads, then forgets them
+    pub fn save_scene(
+        &mut self,
+        file_path: &Path,
+        balls: impl Iterator<Item = (Entity, BallTag)>,
     ) -> std::io::Result<()> {
+        if let Some(dir) = file_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
         let file = File::create(file_path)?;
         let mut writer = BufWriter::new(file);
 
-        for (entity, positions) in &self.positions {
-            if let Ok(ball) = ball_classes.get(*entity) {
-                writeln!(writer, "Class: {:?}", ball.class)?;
-                for (x, z) in positions {
-                    writeln!(writer, "{},{}", x, z)?;
-                }
-                writeln!(writer, "---")?; // Separator between balls
+        for (entity, class) in balls {
+            let Some(positions) = self.positions.remove(&entity) else {
+                continue;
+            };
+            writeln!(writer, "Class: {:?}", class)?;
+            for (x, z) in positions {
+                writeln!(writer, "{},{}", x, z)?;
             }
+            writeln!(writer, "---")?; // Separator between balls
         }
 
         writer.flush()?;
         Ok(())
     }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.frame = 0;
+    }
 }
 
 pub fn track_ball_positions(
     mut ball_positions: Option<ResMut<AggBallPositions>>,
-    query: Query<(Entity, &Transform), With<Ball>>,
+    scene_query: Query<&BallGameScene>,
+    ball_query: Query<&Transform, With<Ball>>,
 ) {
-    if let Some(ball_positions) = &mut ball_positions {
-        for (entity, transform) in query.iter() {
+    let Some(ball_positions) = &mut ball_positions else {
+        return;
+    };
+    ball_positions.frame += 1;
+    if (ball_positions.frame - 1) % ball_positions.stride != 0 {
+        return;
+    }
+    for scene in scene_query.iter() {
+        // completed scenes sit still until the reset
+        if !ball_positions.tracks(scene.id) || scene.completed.is_some() {
+            continue;
+        }
+        let players = scene.players.iter().map(|player| &player.ball);
+        let entities = players.chain(scene.game_balls.iter());
+        for entity in entities {
+            let Ok(transform) = ball_query.get(*entity) else {
+                continue;
+            };
             let position = (transform.translation.x, transform.translation.z);
             ball_positions
                 .positions
-                .entry(entity)
-                .or_insert_with(Vec::new)
+                .entry(*entity)
+                .or_default()
                 .push(position);
         }
     }
 }
+
+/// Writes each tracked scene's positions as the episode ends, before the
+/// scenes are reset, and clears the buffer
+pub fn flush_ball_positions(
+    mut event_reader: EventReader<SimulationEndedEvent>,
+    mut ball_positions: Option<ResMut<AggBallPositions>>,
+    scene_query: Query<&BallGameScene>,
+    ball_query: Query<&Ball>,
+    run_dir: Res<RunDir>,
+) {
+    if event_reader.read().count() == 0 {
+        return;
+    }
+    let Some(ball_positions) = &mut ball_positions else {
+        return;
+    };
+    for scene in scene_query.iter() {
+        if !ball_positions.tracks(scene.id) {
+            continue;
+        }
+        let path = run_dir.join(format!(
+            "positions/scene{:02}_ep{:05}.txt",
+            scene.id, scene.episode
+        ));
+        let players = scene.players.iter().map(|player| &player.ball);
+        let entities = players.chain(scene.game_balls.iter());
+        let balls = entities.filter_map(|entity| {
+            ball_query
+                .get(*entity)
+                .ok()
+                .map(|ball| (*entity, ball.class))
+        });
+        if let Err(e) = ball_positions.save_scene(&path, balls) {
+            eprintln!("Failed to save ball positions to {}: {}", path.display(), e);
+        }
+    }
+    ball_positions.clear();
+}
+
+fn ball_state(transform: &Transform, velocity: &Velocity) -> BallState {
+    BallState {
+        position: Vec2::new(transform.translation.x, transform.translation.z),
+        velocity: Vec2::new(velocity.linvel.x, velocity.linvel.z),
+    }
+}
+
+/// Streams every scene's episode to the run's `recordings/`, one file per episode
+pub fn record_episode_steps(
+    mut scene_query: Query<&mut BallGameScene>,
+    ball_query: Query<(&Transform, &Velocity, &Ball)>,
+    simulation_timer: Res<SimulationTimer>,
+    config: Res<Config>,
+    run_dir: Res<RunDir>,
+) {
+    for mut scene in scene_query.iter_mut() {
+        if scene.completed.is_some() {
+            continue;
+        }
+
+        // start a new file at the beginning of each episode
+        if scene.recorder.is_none() {
+            let header = EpisodeHeader {
+                version: FORMAT_VERSION,
+                seed: scene.seed,
+                scene: scene.id as u32,
+                config: config.resolved().to_string(),
+                ball_classes: ball_query
+                    .iter_many(&scene.game_balls)
+                    .map(|(_, _, ball)| ball.class)
+                    .collect(),
+            };
+            let path = run_dir.join(format!(
+                "recordings/scene{:02}_ep{:05}.btep",
+                scene.id, scene.episode
+            ));
+            match EpisodeWriter::create(&path, &header) {
+                Ok(writer) => scene.recorder = Some(writer),
+                Err(e) => {
+                    eprintln!("Failed to start recording {}: {}", path.display(), e);
+                    continue;
+                }
+            }
+        }
+
+        // the lead player's, recordings hold a single player ball
+        let Ok((p_transform, p_velocity, _)) = ball_query.get(scene.lead().ball) else {
+            continue;
+        };
+        let step = StepRecord {
+            time: simulation_timer.timer.elapsed_secs(),
+            player: ball_state(p_transform, p_velocity),
+            balls: ball_query
+                .iter_many(&scene.game_balls)
+                .map(|(transform, velocity, _)| ball_state(transform, velocity))
+                .collect(),
+            action: scene.lead().last_action,
+            reward: scene.reward_state.last,
+        };
+        if let Err(e) = scene.recorder.as_mut().unwrap().write_step(&step) {
+            eprintln!("Failed to record scene {}: {}", scene.id, e);
+            scene.recorder = None;
+        }
+    }
+}
+
+/// Writes episode outcomes & training stats to the run log,
+/// and to TensorBoard with `--tensorboard`
+pub fn write_metrics(
+    mut run_log: ResMut<RunLog>,
+    mut metrics: Option<ResMut<Metrics>>,
+    mut episode_reader: EventReader<EpisodeEndedEvent>,
+    mut policy_reader: EventReader<PolicyUpdatedEvent>,
+    mut last_update: Local<Option<Instant>>,
+) {
+    // steps per second are measured from the first frame on
+    let last = last_update.get_or_insert_with(Instant::now);
+    let outcomes = episode_reader
+        .read()
+        .map(|event| &event.0)
+        .collect::<Vec<&EpisodeOutcome>>();
+    let mut updates = Vec::new();
+    for update in policy_reader.read() {
+        let now = Instant::now();
+        let elapsed = now.duration_since(*last).as_secs_f32().max(1e-6);
+        updates.push((update, update.stats.steps as f32 / elapsed));
+        *last = now;
+    }
+    if outcomes.is_empty() && updates.is_empty() {
+        return;
+    }
+
+    let result = write_run_log(&mut run_log, &outcomes, &updates);
+    if let Err(e) = result {
+        eprintln!("Failed to write run log: {}", e);
+    }
+    if let Some(metrics) = metrics.as_mut() {
+        if let Err(e) = write_tensorboard(&mut metrics.writer, &outcomes, &updates) {
+            eprintln!("Failed to write metrics: {}", e);
+        }
+    }
+}
+
+fn write_run_log(
+    run_log: &mut RunLog,
+    outcomes: &[&EpisodeOutcome],
+    updates: &[(&PolicyUpdatedEvent, f32)],
+) -> std::io::Result<()> {
+    for outcome in outcomes {
+        run_log.episode(outcome)?;
+    }
+    for (update, steps_per_second) in updates {
+        run_log.update(update.round, &update.stats, *steps_per_second)?;
+    }
+    run_log.flush()
+}
+
+fn write_tensorboard(
+    writer: &mut SummaryWriter,
+    outcomes: &[&EpisodeOutcome],
+    updates: &[(&PolicyUpdatedEvent, f32)],
+) -> std::io::Result<()> {
+    for outcome in outcomes {
+        let tag = format!("episode_return/scene{:02}", outcome.scene);
+        writer.add_scalar(&tag, outcome.episode_return, outcome.episode as i64)?;
+    }
+    let ai = outcomes
+        .iter()
+        .filter(|o| !o.human && !o.scripted)
+        .collect::<Vec<_>>();
+    if let Some(first) = ai.first() {
+        let n = ai.len() as f32;
+        let successes = ai.iter().filter(|o| o.success).count() as f32;
+        let sorted = ai.iter().map(|o| o.final_sorted_fraction).sum::<f32>();
+        let step = first.episode as i64;
+        writer.add_scalar("episode/success_rate", successes / n, step)?;
+        writer.add_scalar("episode/sorted_fraction", sorted / n, step)?;
+    }
+
+    for (update, steps_per_second) in updates {
+        let step = update.round as i64;
+        let stats = &update.stats;
+        writer.add_scalar("train/policy_loss", stats.policy_loss, step)?;
+        writer.add_scalar("train/entropy", stats.entropy, step)?;
+        writer.add_scalar("train/grad_norm", stats.grad_norm, step)?;
+        writer.add_scalar("train/learning_rate", stats.learning_rate as f32, step)?;
+        if let Some(value_loss) = stats.value_loss {
+            writer.add_scalar("train/value_loss", value_loss, step)?;
+        }
+        writer.add_scalar("perf/steps_per_second", *steps_per_second, step)?;
+
+        let actions = update
+            .actions
+            .iter()
+            .map(|a| *a as f32)
+            .collect::<Vec<f32>>();
+        writer.add_histogram("actions", &actions, step)?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,169 @@
+//! Writes TensorBoard event files without depending on TensorFlow
+//!
+//! An event file is a sequence of TFRecords, each
+//! `len u64 | masked_crc32c(len) u32 | data | masked_crc32c(data) u32`,
+//! where `data` is an `Event` protobuf. Only the fields TensorBoard needs
+//! for scalars & histograms are encoded.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::util::checksum::masked_crc32c;
+
+/// number of equal-width buckets histograms are split into
+const HISTOGRAM_BUCKETS: usize = 30;
+
+/// Minimal protobuf encoder, fields are written in the order they're added
+#[derive(Default)]
+struct Proto(Vec<u8>);
+
+impl Proto {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u32) {
+        self.varint(((field << 3) | wire_type) as u64);
+    }
+
+    fn int64(&mut self, field: u32, v: i64) {
+        self.key(field, 0);
+        self.varint(v as u64);
+    }
+
+    fn double(&mut self, field: u32, v: f64) {
+        self.key(field, 1);
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn float(&mut self, field: u32, v: f32) {
+        self.key(field, 5);
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u32, v: &[u8]) {
+        self.key(field, 2);
+        self.varint(v.len() as u64);
+        self.0.extend_from_slice(v);
+    }
+
+    fn packed_doubles(&mut self, field: u32, values: &[f64]) {
+        let data = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<u8>>();
+        self.bytes(field, &data);
+    }
+}
+
+/// `HistogramProto` over `values` with equal-width buckets
+fn histogram(values: &[f32]) -> Proto {
+    let values = values.iter().map(|v| *v as f64).collect::<Vec<f64>>();
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let width = ((max - min) / HISTOGRAM_BUCKETS as f64).max(1e-12);
+
+    let mut counts = vec![0.; HISTOGRAM_BUCKETS];
+    for v in values.iter() {
+        let i = ((v - min) / width) as usize;
+        counts[i.min(HISTOGRAM_BUCKETS - 1)] += 1.;
+    }
+    // each limit is the right edge of its bucket
+    let limits = (1..=HISTOGRAM_BUCKETS)
+        .map(|i| min + width * i as f64)
+        .collect::<Vec<f64>>();
+
+    let mut histo = Proto::default();
+    histo.double(1, min);
+    histo.double(2, max);
+    histo.double(3, values.len() as f64);
+    histo.double(4, values.iter().sum());
+    histo.double(5, values.iter().map(|v| v * v).sum());
+    histo.packed_doubles(6, &limits);
+    histo.packed_doubles(7, &counts);
+    histo
+}
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0., |d| d.as_secs_f64())
+}
+
+/// Appends scalars & histograms to a new event file in `log_dir`
+pub struct SummaryWriter {
+    writer: BufWriter<File>,
+    pub path: PathBuf,
+}
+
+impl SummaryWriter {
+    pub fn create(log_dir: impl AsRef<Path>) -> io::Result<Self> {
+        std::fs::create_dir_all(&log_dir)?;
+        // TensorBoard only picks up files with `tfevents` in the name
+        let name = format!(
+            "events.out.tfevents.{}.balltrainer.{}",
+            wall_time() as u64,
+            std::process::id()
+        );
+        let path = log_dir.as_ref().join(name);
+        let mut writer = SummaryWriter {
+            writer: BufWriter::new(File::create(&path)?),
+            path,
+        };
+
+        let mut event = Proto::default();
+        event.double(1, wall_time());
+        event.bytes(3, b"brain.Event:2");
+        writer.write_record(&event.0)?;
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    fn write_record(&mut self, data: &[u8]) -> io::Result<()> {
+        let len = (data.len() as u64).to_le_bytes();
+        self.writer.write_all(&len)?;
+        self.writer.write_all(&masked_crc32c(&len).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&masked_crc32c(data).to_le_bytes())
+    }
+
+    /// Wraps a `Summary.Value` in an `Event` & writes it
+    fn write_value(&mut self, step: i64, value: Proto) -> io::Result<()> {
+        let mut summary = Proto::default();
+        summary.bytes(1, &value.0);
+
+        let mut event = Proto::default();
+        event.double(1, wall_time());
+        event.int64(2, step);
+        event.bytes(5, &summary.0);
+        self.write_record(&event.0)
+    }
+
+    pub fn add_scalar(&mut self, tag: &str, value: f32, step: i64) -> io::Result<()> {
+        let mut v = Proto::default();
+        v.bytes(1, tag.as_bytes());
+        v.float(2, value);
+        self.write_value(step, v)
+    }
+
+    /// Skipped when `values` is empty, TensorBoard can't show empty histograms
+    pub fn add_histogram(&mut self, tag: &str, values: &[f32], step: i64) -> io::Result<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        let mut v = Proto::default();
+        v.bytes(1, tag.as_bytes());
+        v.bytes(5, &histogram(values).0);
+        self.write_value(step, v)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}